@@ -0,0 +1,80 @@
+use std::env;
+
+use gst::{prelude::{Cast, ObjectExt, GstBinExtManual}, Element, traits::ElementExt};
+
+// Pulls captured frames out of an appsink into tightly packed Vec<u8>s, e.g. to feed an ML model
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        println!("Invalid usage!");
+        println!("Usage: {} xid_to_capture", args[0]);
+        return;
+    }
+
+    let xid = if args[1].starts_with("0x") {
+        u32::from_str_radix(args[1].trim_start_matches("0x"), 16).expect("Failed to parse hex string!")
+    } else {
+        args[1].parse().expect("Failed to parse u32!")
+    };
+
+    gst::init().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+
+    let ximageredux = ximageredux::XImageRedux::default();
+    ximageredux.set_property("xid", xid);
+
+    let videoconvert = gst::ElementFactory::make("videoconvert").build().unwrap();
+    let appsink = gst_app::AppSink::builder()
+        .caps(&gst::Caps::builder("video/x-raw").field("format", "BGRx").build())
+        .build();
+
+    pipeline.add_many(&[
+        ximageredux.upcast_ref::<gst::Element>(),
+        &videoconvert,
+        appsink.upcast_ref::<gst::Element>()
+    ]).unwrap();
+
+    Element::link_many(&[ximageredux.upcast_ref::<gst::Element>(), &videoconvert, appsink.upcast_ref::<gst::Element>()]).unwrap();
+
+    appsink.set_callbacks(gst_app::AppSinkCallbacks::builder()
+        .new_sample(|sink| {
+            let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+            let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
+            let info = sample.caps()
+                .and_then(|caps| gst_video::VideoInfo::from_caps(caps).ok())
+                .ok_or(gst::FlowError::NotNegotiated)?;
+
+            // Maps read-only without copying, the stride comes from the buffer's GstVideoMeta
+            let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, &info).map_err(|_| gst::FlowError::Error)?;
+            let stride = frame.plane_stride()[0] as usize;
+            let data = frame.plane_data(0).map_err(|_| gst::FlowError::Error)?;
+
+            let row_len = info.width() as usize * 4;
+            let mut pixels = Vec::with_capacity(row_len * info.height() as usize);
+            for row in data.chunks(stride).take(info.height() as usize) {
+                pixels.extend_from_slice(&row[..row_len]);
+            }
+
+            println!("Got a {}x{} frame ({} bytes)", info.width(), info.height(), pixels.len());
+
+            Ok(gst::FlowSuccess::Ok)
+        })
+        .build());
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let bus = pipeline.bus().unwrap();
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        match msg.view() {
+            gst::MessageView::Eos(..) => break,
+            gst::MessageView::Error(err) => {
+                println!("Error: {}", err.error());
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
@@ -49,10 +49,17 @@ async fn main() {
 
     ximageredux.connect_notify(None, |x, param| {
         match param.name() {
+            "xid" => println!("Capturing window {:#x}", x.property::<u32>("xid")),
             "width" => println!("New width: {}", x.property::<u32>("width")),
             "height" => println!("New height: {}", x.property::<u32>("height")),
             "visibility" => println!("New visibility: {:?}", x.property::<ximageredux::WindowVisibility>("visibility")),
-            _ => unreachable!()
+            "fullscreen" => println!("Fullscreen: {}", x.property::<bool>("fullscreen")),
+            "title" => println!("New title: {:?}", x.property::<Option<String>>("title")),
+            "scale-factor" => println!("New scale factor: {}", x.property::<f64>("scale-factor")),
+            "stats" => println!("Stats: {:?}", x.property::<Option<gst::Structure>>("stats")),
+            "last-frame-was-fresh" => println!("Last frame was fresh: {}", x.property::<bool>("last-frame-was-fresh")),
+            // Anything else the element notifies isn't interesting here
+            _ => {}
         }
     });
 
@@ -0,0 +1,103 @@
+// Prints the windows ximageredux can capture, so the xid can be found without xwininfo
+use std::{env, process};
+
+use ximageredux::{list_windows, WindowInfo};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let json = match args.get(1).map(String::as_str) {
+        None => false,
+        Some("--json") if args.len() == 2 => true,
+        _ => {
+            println!("Invalid usage!");
+            println!("Usage: {} [--json]", args[0]);
+            process::exit(2);
+        }
+    };
+
+    let windows = match list_windows(None) {
+        Ok(windows) => windows,
+        Err(e) => {
+            eprintln!("Failed to list windows: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if json {
+        print_json(&windows);
+    } else {
+        print_table(&windows);
+    }
+}
+
+fn print_table(windows: &[WindowInfo]) {
+    let rows: Vec<[String; 4]> = windows.iter().map(|info| [
+        format!("{:#x}", info.xid),
+        format!("{}x{}", info.width, info.height),
+        info.class.as_ref().map(|(_, class)| class.clone()).unwrap_or_default(),
+        info.title.clone().unwrap_or_default()
+    ]).collect();
+
+    let header = ["XID", "SIZE", "CLASS", "TITLE"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    // The title is last so it isn't padded
+    let print_row = |cells: [&str; 4]| {
+        println!("{:<w0$}  {:<w1$}  {:<w2$}  {}", cells[0], cells[1], cells[2], cells[3], w0 = widths[0], w1 = widths[1], w2 = widths[2]);
+    };
+
+    print_row(header);
+    for row in &rows {
+        print_row([&row[0], &row[1], &row[2], &row[3]]);
+    }
+}
+
+fn print_json(windows: &[WindowInfo]) {
+    let entries: Vec<String> = windows.iter().map(|info| {
+        let (instance, class) = info.class.clone().unzip();
+
+        format!(
+            "{{\"xid\":{},\"title\":{},\"instance\":{},\"class\":{},\"x\":{},\"y\":{},\"width\":{},\"height\":{}}}",
+            info.xid,
+            json_string(info.title.as_deref()),
+            json_string(instance.as_deref()),
+            json_string(class.as_deref()),
+            info.x,
+            info.y,
+            info.width,
+            info.height
+        )
+    }).collect();
+
+    println!("[{}]", entries.join(","));
+}
+
+// Quoted and escaped, or null
+fn json_string(value: Option<&str>) -> String {
+    let value = match value {
+        Some(value) => value,
+        None => return "null".to_owned()
+    };
+
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+
+    out
+}
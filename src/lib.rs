@@ -1,9 +1,12 @@
 use gst::glib;
 pub mod ximageredux;
+pub mod windowprovider;
 pub use crate::ximageredux::*;
+pub use crate::windowprovider::{WindowProvider, WindowDevice};
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     ximageredux::register(plugin)?;
+    windowprovider::register(plugin)?;
     Ok(())
 }
 
@@ -27,4 +30,163 @@ pub enum WindowVisibility {
     Unknown = 0,
     Visible = 1,
     Hidden = 2
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxCursorFallback")]
+#[repr(i32)]
+pub enum CursorFallback {
+    #[default]
+    #[enum_value(name = "No cursor when XFixes is unavailable", nick = "none")]
+    None = 0,
+    #[enum_value(name = "Draw a synthetic arrow when XFixes is unavailable, won't match the real cursor shape", nick = "synthetic")]
+    Synthetic = 1
+}
+
+// When the cursor is drawn, on top of show-cursor and it being over the capture region
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxCursorMode")]
+#[repr(i32)]
+pub enum CursorMode {
+    #[default]
+    #[enum_value(name = "Whenever the pointer is over the captured area, even over a window covering it", nick = "always")]
+    Always = 0,
+    #[enum_value(name = "Never draw the cursor", nick = "never")]
+    Never = 1,
+    #[enum_value(name = "Only while the pointer is over the captured window itself", nick = "only-when-inside-window")]
+    OnlyWhenInsideWindow = 2,
+    #[enum_value(name = "Only while the captured window has focus", nick = "only-when-window-focused")]
+    OnlyWhenWindowFocused = 3
+}
+
+// Matched against _NET_WM_WINDOW_TYPE when resolving the window to capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxWindowType")]
+#[repr(i32)]
+pub enum WindowType {
+    #[default]
+    #[enum_value(name = "Any window type", nick = "any")]
+    Any = 0,
+    #[enum_value(name = "_NET_WM_WINDOW_TYPE_NORMAL", nick = "normal")]
+    Normal = 1,
+    #[enum_value(name = "_NET_WM_WINDOW_TYPE_DIALOG", nick = "dialog")]
+    Dialog = 2,
+    #[enum_value(name = "_NET_WM_WINDOW_TYPE_UTILITY", nick = "utility")]
+    Utility = 3,
+    #[enum_value(name = "_NET_WM_WINDOW_TYPE_TOOLBAR", nick = "toolbar")]
+    Toolbar = 4,
+    #[enum_value(name = "_NET_WM_WINDOW_TYPE_MENU", nick = "menu")]
+    Menu = 5,
+    #[enum_value(name = "_NET_WM_WINDOW_TYPE_SPLASH", nick = "splash")]
+    Splash = 6,
+    #[enum_value(name = "_NET_WM_WINDOW_TYPE_DOCK", nick = "dock")]
+    Dock = 7,
+    #[enum_value(name = "_NET_WM_WINDOW_TYPE_DESKTOP", nick = "desktop")]
+    Desktop = 8
+}
+
+// What crop-x/crop-y are relative to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxCropCoordinates")]
+#[repr(i32)]
+pub enum CropCoordinates {
+    #[default]
+    #[enum_value(name = "Relative to the captured window", nick = "window")]
+    Window = 0,
+    #[enum_value(name = "Relative to the screen root", nick = "root")]
+    Root = 1
+}
+
+// Clock used for buffer timestamps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxTimestampMode")]
+#[repr(i32)]
+pub enum TimestampMode {
+    #[default]
+    #[enum_value(name = "Pipeline running time", nick = "running-time")]
+    RunningTime = 0,
+    #[enum_value(name = "Monotonic system time", nick = "monotonic")]
+    Monotonic = 1,
+    #[enum_value(name = "X server time from the latest X event, extrapolated locally", nick = "x-server-time")]
+    XServerTime = 2
+}
+
+// How the element decides when to grab a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxCaptureBackend")]
+#[repr(i32)]
+pub enum CaptureBackend {
+    #[default]
+    #[enum_value(name = "Grab with GetImage whenever a frame is due", nick = "get-image")]
+    GetImage = 0,
+    #[enum_value(name = "Grab right after the window presents a complete frame with the Present extension", nick = "present")]
+    Present = 1
+}
+
+// Backing-store hint set on the captured window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxBackingStore")]
+#[repr(i32)]
+pub enum BackingStore {
+    #[default]
+    #[enum_value(name = "Leave the window's backing-store attribute alone", nick = "unchanged")]
+    Unchanged = 0,
+    #[enum_value(name = "Ask the server to keep obscured contents while the window is mapped", nick = "when-mapped")]
+    WhenMapped = 1,
+    #[enum_value(name = "Ask the server to always keep the window's contents", nick = "always")]
+    Always = 2
+}
+
+// Format frames are pushed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxOutputFormat")]
+#[repr(i32)]
+pub enum OutputFormat {
+    #[default]
+    #[enum_value(name = "The window's RGB format", nick = "native")]
+    Native = 0,
+    #[enum_value(name = "Planar 4:2:0 YUV", nick = "i420")]
+    I420 = 1,
+    #[enum_value(name = "Semi-planar 4:2:0 YUV", nick = "nv12")]
+    Nv12 = 2
+}
+
+// Matrix used when converting to a YUV output-format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxColorimetry")]
+#[repr(i32)]
+pub enum Colorimetry {
+    #[enum_value(name = "ITU-R BT.601, for SD content", nick = "bt601")]
+    Bt601 = 0,
+    #[default]
+    #[enum_value(name = "ITU-R BT.709, for HD content", nick = "bt709")]
+    Bt709 = 1
+}
+
+// Window captured in place of an explicitly set xid that isn't a toplevel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxToplevelMode")]
+#[repr(i32)]
+pub enum ToplevelMode {
+    #[enum_value(name = "Capture the xid as given, even if it's a child window", nick = "none")]
+    None = 0,
+    #[default]
+    #[enum_value(name = "The nearest ancestor the window manager manages, the window without decorations", nick = "client")]
+    Client = 1,
+    #[enum_value(name = "The ancestor directly under the root, usually the window manager's frame", nick = "frame")]
+    Frame = 2
+}
+
+// How window-name is compared against titles and WM_CLASS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstXImageReduxNameMatch")]
+#[repr(i32)]
+pub enum NameMatch {
+    #[default]
+    #[enum_value(name = "The whole name must match exactly", nick = "exact")]
+    Exact = 0,
+    #[enum_value(name = "The name must contain window-name, ignoring case", nick = "substring")]
+    Substring = 1,
+    #[enum_value(name = "window-name is a regular expression searched for in the name", nick = "regex")]
+    Regex = 2
+}
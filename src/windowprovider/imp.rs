@@ -0,0 +1,141 @@
+use anyhow::Result;
+use gst::{glib, subclass::prelude::{ObjectSubclass, ObjectImpl, GstObjectImpl, DeviceImpl, DeviceProviderImpl, ObjectSubclassExt}, prelude::{Cast, DeviceExt, ObjectExt}};
+use once_cell::sync::Lazy;
+
+use gst::{debug, warning};
+
+use crate::ximageredux::{XImageRedux, window};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "ximageredux-windowprovider",
+        gst::DebugColorFlags::empty(),
+        Some("X11 Window Device Provider"),
+    )
+});
+
+// Key in the device properties holding the window to capture
+const XID_PROPERTY: &str = "ximageredux.xid";
+
+#[derive(Default)]
+pub struct WindowProvider {}
+
+impl WindowProvider {
+    // Uses its own short-lived connection, probing is rare and shouldn't keep one open
+    fn scan(&self) -> Result<Vec<gst::Device>> {
+        Ok(window::list_windows(None)?.into_iter().map(|info| {
+            let (instance, class) = info.class.unzip();
+
+            let display_name = info.title.clone()
+                .filter(|title| !title.is_empty())
+                .or_else(|| class.clone())
+                .unwrap_or_else(|| format!("Window {:#x}", info.xid));
+
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("width", info.width as i32)
+                .field("height", info.height as i32)
+                .build();
+
+            let properties = gst::Structure::builder("ximageredux-window")
+                .field(XID_PROPERTY, info.xid)
+                .field("ximageredux.title", info.title)
+                .field("ximageredux.instance", instance)
+                .field("ximageredux.class", class)
+                .field("ximageredux.x", info.x as i32)
+                .field("ximageredux.y", info.y as i32)
+                .field("ximageredux.width", info.width as u32)
+                .field("ximageredux.height", info.height as u32)
+                .build();
+
+            glib::Object::builder::<super::WindowDevice>()
+                .property("display-name", display_name)
+                .property("device-class", "Source/Video")
+                .property("caps", caps)
+                .property("properties", properties)
+                .build()
+                .upcast()
+        }).collect())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for WindowProvider {
+    const NAME: &'static str = "XImageReduxWindowProvider";
+    type Type = super::WindowProvider;
+    type ParentType = gst::DeviceProvider;
+}
+
+impl ObjectImpl for WindowProvider {}
+
+impl GstObjectImpl for WindowProvider {}
+
+impl DeviceProviderImpl for WindowProvider {
+    fn metadata() -> Option<&'static gst::subclass::DeviceProviderMetadata> {
+        static DEVICE_PROVIDER_METADATA: Lazy<gst::subclass::DeviceProviderMetadata> = Lazy::new(|| {
+            gst::subclass::DeviceProviderMetadata::new(
+                "X11 Window Provider",
+                "Source/Video",
+                "Lists X11 toplevel windows that ximageredux can capture",
+                "Jack Hogan",
+            )
+        });
+
+        Some(&*DEVICE_PROVIDER_METADATA)
+    }
+
+    fn probe(&self) -> Vec<gst::Device> {
+        match self.scan() {
+            Ok(devices) => {
+                debug!(CAT, imp: self, "Found {} windows", devices.len());
+                devices
+            }
+            Err(e) => {
+                warning!(CAT, imp: self, "Failed to list windows: {}", e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct WindowDevice {}
+
+impl WindowDevice {
+    fn xid(&self) -> Result<u32, gst::LoggableError> {
+        self.obj().properties()
+            .and_then(|properties| properties.get::<u32>(XID_PROPERTY).ok())
+            .ok_or_else(|| gst::loggable_error!(CAT, "Device has no window"))
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for WindowDevice {
+    const NAME: &'static str = "XImageReduxWindowDevice";
+    type Type = super::WindowDevice;
+    type ParentType = gst::Device;
+}
+
+impl ObjectImpl for WindowDevice {}
+
+impl GstObjectImpl for WindowDevice {}
+
+impl DeviceImpl for WindowDevice {
+    fn create_element(&self, name: Option<&str>) -> Result<gst::Element, gst::LoggableError> {
+        let element = glib::Object::builder::<XImageRedux>()
+            .property("name", name)
+            .property("xid", self.xid()?)
+            .build();
+
+        Ok(element.upcast())
+    }
+
+    fn reconfigure_element(&self, element: &gst::Element) -> Result<(), gst::LoggableError> {
+        match element.downcast_ref::<XImageRedux>() {
+            Some(element) => {
+                element.set_property("xid", self.xid()?);
+                Ok(())
+            }
+            None => Err(gst::loggable_error!(CAT, "Only ximageredux elements can be reconfigured"))
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use gst::{glib, prelude::StaticType};
+
+mod imp;
+
+glib::wrapper! {
+    pub struct WindowProvider(ObjectSubclass<imp::WindowProvider>) @extends gst::DeviceProvider, gst::Object;
+}
+
+glib::wrapper! {
+    pub struct WindowDevice(ObjectSubclass<imp::WindowDevice>) @extends gst::Device, gst::Object;
+}
+
+unsafe impl Send for WindowProvider {}
+unsafe impl Sync for WindowProvider {}
+
+unsafe impl Send for WindowDevice {}
+unsafe impl Sync for WindowDevice {}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::DeviceProvider::register(
+        Some(plugin),
+        "ximageredux-windowprovider",
+        gst::Rank::Secondary,
+        WindowProvider::static_type(),
+    )
+}
@@ -0,0 +1,271 @@
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use gst_video::{VideoFormat, VideoFormatInfo, VideoFrameFlags, VideoFrameRef, VideoInfo, VideoMeta, VideoOverlayComposition, VideoOverlayFormatFlags, VideoOverlayRectangle};
+
+// Cursor bitmap in premultiplied ARGB, as returned by XFixes
+#[derive(Debug, Clone)]
+pub struct CursorImage {
+    pub width: u16,
+    pub height: u16,
+    pub xhot: u16,
+    pub yhot: u16,
+    pub pixels: Vec<u32>
+}
+
+// Upper bound on decoding or converting a custom cursor image
+const CUSTOM_CURSOR_TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(5);
+
+// Click highlight ring, drawn centred on the pointer
+const CLICK_RING_RADIUS: f32 = 18.0;
+const CLICK_RING_WIDTH: f32 = 3.0;
+const CLICK_RING_COLOR: [u32; 3] = [0xFF, 0xC8, 0x00];
+
+// Classic arrow pointer, 'X' is outline, '.' is fill
+const SYNTHETIC_ARROW: [&str; 19] = [
+    "X           ",
+    "XX          ",
+    "X.X         ",
+    "X..X        ",
+    "X...X       ",
+    "X....X      ",
+    "X.....X     ",
+    "X......X    ",
+    "X.......X   ",
+    "X........X  ",
+    "X.........X ",
+    "X......XXXXX",
+    "X...X..X    ",
+    "X..XX..X    ",
+    "X.X  X..X   ",
+    "XX   X..X   ",
+    "X     X..X  ",
+    "      X..X  ",
+    "       XX   ",
+];
+
+impl CursorImage {
+    // Simple arrow used when the real cursor can't be fetched, doesn't match the real cursor shape
+    pub fn synthetic() -> Self {
+        let pixels = SYNTHETIC_ARROW.iter()
+            .flat_map(|row| row.chars())
+            .map(|c| match c {
+                'X' => 0xFF000000,
+                '.' => 0xFFFFFFFF,
+                _ => 0
+            })
+            .collect();
+
+        Self {
+            width: SYNTHETIC_ARROW[0].len() as u16,
+            height: SYNTHETIC_ARROW.len() as u16,
+            xhot: 0,
+            yhot: 0,
+            pixels
+        }
+    }
+
+    // Resampled bilinearly, which is correct on premultiplied pixels, with the hotspot kept on the same spot
+    pub fn scaled(&self, factor: f64) -> Self {
+        let width = ((f64::from(self.width) * factor).round() as u16).max(1);
+        let height = ((f64::from(self.height) * factor).round() as u16).max(1);
+        if self.pixels.is_empty() || (width, height) == (self.width, self.height) {
+            return self.clone();
+        }
+
+        let (src_width, src_height) = (usize::from(self.width), usize::from(self.height));
+        let scale_x = f64::from(width) / f64::from(self.width);
+        let scale_y = f64::from(height) / f64::from(self.height);
+        let channel = |x: usize, y: usize, shift: u32| f64::from((self.pixels[y * src_width + x] >> shift) & 0xFF);
+
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                // Destination pixel centres mapped back into the source
+                let sx = ((f64::from(x) + 0.5) / scale_x - 0.5).clamp(0.0, (src_width - 1) as f64);
+                let sy = ((f64::from(y) + 0.5) / scale_y - 0.5).clamp(0.0, (src_height - 1) as f64);
+                let (x0, y0) = (sx as usize, sy as usize);
+                let (x1, y1) = ((x0 + 1).min(src_width - 1), (y0 + 1).min(src_height - 1));
+                let (fx, fy) = (sx - x0 as f64, sy - y0 as f64);
+
+                [24, 16, 8, 0].into_iter().fold(0, |px, shift| {
+                    let top = channel(x0, y0, shift) * (1.0 - fx) + channel(x1, y0, shift) * fx;
+                    let bottom = channel(x0, y1, shift) * (1.0 - fx) + channel(x1, y1, shift) * fx;
+                    px | ((top * (1.0 - fy) + bottom * fy).round() as u32) << shift
+                })
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            xhot: ((f64::from(self.xhot) * scale_x) as u16).min(width - 1),
+            yhot: ((f64::from(self.yhot) * scale_y) as u16).min(height - 1),
+            pixels
+        }
+    }
+
+    // Ring around the pointer for highlight-clicks, fade goes from 1 (just clicked) to 0 (gone)
+    pub fn click_ring(fade: f32) -> Self {
+        let size = (CLICK_RING_RADIUS + CLICK_RING_WIDTH).ceil() as u16 * 2 + 1;
+        let center = f32::from(size / 2);
+
+        let pixels = (0..size)
+            .flat_map(|y| (0..size).map(move |x| (f32::from(x), f32::from(y))))
+            .map(|(x, y)| {
+                // Antialiased by coverage of the ring's width
+                let distance = ((x - center).powi(2) + (y - center).powi(2)).sqrt();
+                let coverage = (CLICK_RING_WIDTH / 2.0 - (distance - CLICK_RING_RADIUS).abs() + 0.5).clamp(0.0, 1.0);
+                let alpha = (coverage * fade.clamp(0.0, 1.0) * 255.0) as u32;
+
+                let [r, g, b] = CLICK_RING_COLOR.map(|c| c * alpha / 255);
+                alpha << 24 | r << 16 | g << 8 | b
+            })
+            .collect();
+
+        Self {
+            width: size,
+            height: size,
+            xhot: size / 2,
+            yhot: size / 2,
+            pixels
+        }
+    }
+}
+
+// Loads a PNG to draw in place of the real cursor, decoded with whatever PNG decoder GStreamer has
+pub fn load_custom(path: &Path, xhot: u16, yhot: u16) -> Result<CursorImage> {
+    let buffer = gst::Buffer::from_mut_slice(std::fs::read(path)?);
+    let sample = gst::Sample::builder()
+        .buffer(&buffer)
+        .caps(&gst::Caps::builder("image/png").build())
+        .build();
+
+    from_sample(&sample, xhot, yhot)
+}
+
+// Converts a custom cursor sample to premultiplied ARGB, any raw video format GStreamer can convert works
+pub fn from_sample(sample: &gst::Sample, xhot: u16, yhot: u16) -> Result<CursorImage> {
+    let caps = gst_video::VideoCapsBuilder::new().format(VideoFormat::Bgra).build();
+    let sample = gst_video::convert_sample(sample, &caps, CUSTOM_CURSOR_TIMEOUT)?;
+
+    let (info, buffer) = match (sample.caps().map(VideoInfo::from_caps), sample.buffer()) {
+        (Some(info), Some(buffer)) => (info?, buffer),
+        _ => bail!("Converted cursor image is missing caps or a buffer")
+    };
+
+    let (width, height) = match (u16::try_from(info.width()), u16::try_from(info.height())) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => bail!("Cursor image is too large ({}x{})", info.width(), info.height())
+    };
+
+    let frame = VideoFrameRef::from_buffer_ref_readable(buffer, &info)?;
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data(0)?;
+
+    // Images come with straight alpha, blending expects it premultiplied like XFixes cursors
+    let pixels = (0..height as usize)
+        .flat_map(|y| data[y * stride..][..width as usize * 4].chunks_exact(4))
+        .map(|px| {
+            let [b, g, r, a] = [px[0], px[1], px[2], px[3]].map(u32::from);
+            let [r, g, b] = [r, g, b].map(|c| c * a / 255);
+            a << 24 | r << 16 | g << 8 | b
+        })
+        .collect();
+
+    Ok(CursorImage {
+        width,
+        height,
+        xhot: xhot.min(width.saturating_sub(1)),
+        yhot: yhot.min(height.saturating_sub(1)),
+        pixels
+    })
+}
+
+// Byte offsets of the red, green, and blue components for packed 8-bit RGB formats
+pub fn rgb_offsets(info: &VideoFormatInfo) -> Option<(usize, [usize; 3])> {
+    if !info.is_rgb() || info.n_planes() != 1 || info.bits() != 8 {
+        return None;
+    }
+
+    let pixel_stride = info.pixel_stride()[0] as usize;
+    if pixel_stride != 3 && pixel_stride != 4 {
+        return None;
+    }
+
+    let offsets = info.poffset();
+    Some((pixel_stride, [offsets[0] as usize, offsets[1] as usize, offsets[2] as usize]))
+}
+
+// Alpha-blends the cursor into a packed RGB frame with its hotspot at (x, y), clipping at the frame edges
+// Returns false if the frame format can't be blended into
+pub fn blend(frame: &mut [u8], stride: usize, info: &VideoInfo, cursor: &CursorImage, x: i32, y: i32) -> bool {
+    let (pixel_stride, [r, g, b]) = match rgb_offsets(&info.format_info()) {
+        Some(offsets) => offsets,
+        None => return false
+    };
+
+    let origin_x = x - cursor.xhot as i32;
+    let origin_y = y - cursor.yhot as i32;
+
+    for cy in 0..cursor.height as i32 {
+        let fy = origin_y + cy;
+        if fy < 0 || fy >= info.height() as i32 {
+            continue;
+        }
+
+        for cx in 0..cursor.width as i32 {
+            let fx = origin_x + cx;
+            if fx < 0 || fx >= info.width() as i32 {
+                continue;
+            }
+
+            let pixel = cursor.pixels[(cy * cursor.width as i32 + cx) as usize];
+            let alpha = pixel >> 24;
+            if alpha == 0 {
+                continue;
+            }
+
+            let idx = fy as usize * stride + fx as usize * pixel_stride;
+            if idx + pixel_stride > frame.len() {
+                continue;
+            }
+
+            // Cursor is premultiplied, so only the destination is scaled
+            let inv = 255 - alpha;
+            for (offset, shift) in [(r, 16), (g, 8), (b, 0)] {
+                let src = (pixel >> shift) & 0xFF;
+                let dst = frame[idx + offset] as u32;
+                frame[idx + offset] = (src + dst * inv / 255).min(255) as u8;
+            }
+        }
+    }
+
+    true
+}
+
+// Wraps the images in an overlay composition with their hotspots at (x, y), for downstream to render or drop
+// Later images are drawn over earlier ones
+pub fn overlay(images: &[&CursorImage], x: i32, y: i32) -> Result<VideoOverlayComposition> {
+    // Native-endian ARGB words are BGRA bytes on little-endian hosts
+    let format = if cfg!(target_endian = "little") { VideoFormat::Bgra } else { VideoFormat::Argb };
+
+    let mut rects = Vec::with_capacity(images.len());
+    for image in images {
+        let bytes: Vec<u8> = image.pixels.iter().flat_map(|px| px.to_ne_bytes()).collect();
+
+        let mut buffer = gst::Buffer::from_mut_slice(bytes);
+        VideoMeta::add(buffer.get_mut().unwrap(), VideoFrameFlags::empty(), format, image.width.into(), image.height.into())?;
+
+        rects.push(VideoOverlayRectangle::new_raw(
+            &buffer,
+            x - i32::from(image.xhot),
+            y - i32::from(image.yhot),
+            image.width.into(),
+            image.height.into(),
+            VideoOverlayFormatFlags::PREMULTIPLIED_ALPHA
+        ));
+    }
+
+    Ok(VideoOverlayComposition::new(rects.iter())?)
+}
@@ -0,0 +1,360 @@
+use std::{ffi::c_void, mem, ptr};
+
+use anyhow::{Result, bail};
+use gst::glib::{self, translate::{ToGlibPtr, from_glib_full}};
+use gst::prelude::*;
+use gst_gl::prelude::*;
+
+// Context type applications use to hand over a context to share textures with
+pub const APP_CONTEXT_TYPE: &str = "gst.gl.app_context";
+
+const EGL_NATIVE_PIXMAP_KHR: u32 = 0x30B0;
+const EGL_IMAGE_PRESERVED_KHR: i32 = 0x30D2;
+const EGL_TRUE: i32 = 1;
+const EGL_NONE: i32 = 0x3038;
+
+const GL_TEXTURE_2D: u32 = 0x0DE1;
+const GL_FRAMEBUFFER: u32 = 0x8D40;
+const GL_READ_FRAMEBUFFER: u32 = 0x8CA8;
+const GL_DRAW_FRAMEBUFFER: u32 = 0x8CA9;
+const GL_COLOR_ATTACHMENT0: u32 = 0x8CE0;
+const GL_FRAMEBUFFER_COMPLETE: u32 = 0x8CD5;
+const GL_COLOR_BUFFER_BIT: u32 = 0x4000;
+const GL_NEAREST: u32 = 0x2600;
+
+type CreateImage = unsafe extern "C" fn(*mut c_void, *mut c_void, u32, *mut c_void, *const i32) -> *mut c_void;
+type BlitFramebuffer = unsafe extern "C" fn(i32, i32, i32, i32, i32, i32, i32, i32, u32, u32);
+
+// Entry points looked up through the context, EGL_KHR_image_pixmap and OES_EGL_image aren't part of any GL version
+#[derive(Clone, Copy)]
+struct Functions {
+    create_image: CreateImage,
+    destroy_image: unsafe extern "C" fn(*mut c_void, *mut c_void) -> u32,
+    image_target_texture: unsafe extern "C" fn(u32, *mut c_void),
+    gen_textures: unsafe extern "C" fn(i32, *mut u32),
+    delete_textures: unsafe extern "C" fn(i32, *const u32),
+    bind_texture: unsafe extern "C" fn(u32, u32),
+    gen_framebuffers: unsafe extern "C" fn(i32, *mut u32),
+    delete_framebuffers: unsafe extern "C" fn(i32, *const u32),
+    bind_framebuffer: unsafe extern "C" fn(u32, u32),
+    framebuffer_texture_2d: unsafe extern "C" fn(u32, u32, u32, u32, i32),
+    check_framebuffer_status: unsafe extern "C" fn(u32) -> u32,
+    blit_framebuffer: BlitFramebuffer
+}
+
+impl Functions {
+    fn load(context: &gst_gl::GLContext) -> Result<Self> {
+        // Each name is paired with its signature from the EGL and GL headers
+        unsafe {
+            Ok(Self {
+                create_image: load(context, "eglCreateImageKHR")?,
+                destroy_image: load(context, "eglDestroyImageKHR")?,
+                image_target_texture: load(context, "glEGLImageTargetTexture2DOES")?,
+                gen_textures: load(context, "glGenTextures")?,
+                delete_textures: load(context, "glDeleteTextures")?,
+                bind_texture: load(context, "glBindTexture")?,
+                gen_framebuffers: load(context, "glGenFramebuffers")?,
+                delete_framebuffers: load(context, "glDeleteFramebuffers")?,
+                bind_framebuffer: load(context, "glBindFramebuffer")?,
+                framebuffer_texture_2d: load(context, "glFramebufferTexture2D")?,
+                check_framebuffer_status: load(context, "glCheckFramebufferStatus")?,
+                blit_framebuffer: load(context, "glBlitFramebuffer")?
+            })
+        }
+    }
+}
+
+unsafe fn load<F: Copy>(context: &gst_gl::GLContext, name: &str) -> Result<F> {
+    debug_assert_eq!(mem::size_of::<F>(), mem::size_of::<usize>());
+
+    let address = context.proc_address(name);
+    if address == 0 {
+        bail!("{} is unavailable", name);
+    }
+
+    Ok(mem::transmute_copy(&address))
+}
+
+// Display and contexts for GLMemory output, filled in by set_context and decide_allocation
+#[derive(Default)]
+pub struct Gl {
+    display: Option<gst_gl::GLDisplay>,
+    // Set by the application, textures are shared with it
+    other_context: Option<gst_gl::GLContext>,
+    context: Option<gst_gl::GLContext>,
+    functions: Option<Functions>,
+    // EGLDisplay of the context, the pixmap is bound through it
+    egl_display: usize,
+    // GLMemory caps were negotiated, frames are blitted from the offscreen pixmap
+    pub negotiated: bool
+}
+
+impl Gl {
+    pub fn has_display(&self) -> bool {
+        self.display.is_some()
+    }
+
+    pub fn has_other_context(&self) -> bool {
+        self.other_context.is_some()
+    }
+
+    pub fn set_context(&mut self, element: &gst::Element, context: &gst::Context) {
+        let (display, other_context) = gst_gl::functions::gl_handle_set_context(element, context);
+
+        if display.is_some() {
+            self.display = display;
+        }
+
+        if other_context.is_some() {
+            self.other_context = other_context;
+        }
+    }
+
+    // Answers GL context queries from downstream with what this element uses
+    pub fn handle_query(&self, element: &gst::Element, query: &mut gst::query::Context) -> bool {
+        gst_gl::functions::gl_handle_context_query(element, query, self.display.as_ref(), self.context.as_ref(), self.other_context.as_ref())
+    }
+
+    // Creates the context on first use, unless downstream already has one on the same display, and a pool of textures for caps
+    pub fn prepare(&mut self, local: Option<gst_gl::GLContext>, caps: &gst::Caps) -> Result<(gst::BufferPool, u32)> {
+        let display = match self.display.clone() {
+            Some(display) => display,
+            None => bail!("No GL display")
+        };
+
+        let context = match (self.context.clone(), local.filter(|local| local.display() == display)) {
+            (Some(context), _) | (None, Some(context)) => context,
+            (None, None) => {
+                let lock = display.object_lock();
+                match gst_gl::GLDisplay::get_gl_context_for_current_thread(&lock) {
+                    Some(context) => context,
+                    None => {
+                        let context = gst_gl::GLDisplay::create_context(&lock, self.other_context.as_ref())?;
+                        gst_gl::GLDisplay::add_context(&lock, &context)?;
+                        context
+                    }
+                }
+            }
+        };
+
+        if self.functions.is_none() {
+            // GLX contexts can't bind EGL images, GST_GL_PLATFORM=egl picks EGL on X11
+            if !context.gl_platform().contains(gst_gl::GLPlatform::EGL) {
+                bail!("Binding the pixmap needs an EGL context, got {:?}", context.gl_platform());
+            }
+
+            for feature in ["EGL_KHR_image_pixmap", "GL_OES_EGL_image"] {
+                if !context.check_feature(feature) {
+                    bail!("The GL context doesn't support {}", feature);
+                }
+            }
+
+            // glBlitFramebuffer is core from OpenGL 3.0 and OpenGL ES 3.0
+            if !context.check_gl_version(gst_gl::GLAPI::OPENGL | gst_gl::GLAPI::OPENGL3, 3, 0) && !context.check_gl_version(gst_gl::GLAPI::GLES2, 3, 0) {
+                bail!("The GL context is older than OpenGL 3.0 or OpenGL ES 3.0");
+            }
+
+            let egl_display = match gst_gl_egl::GLDisplayEGL::from_gl_display(&display) {
+                Some(egl_display) => unsafe { gst_gl::ffi::gst_gl_display_get_handle(egl_display.upcast_ref::<gst_gl::GLDisplay>().to_glib_none().0) },
+                None => bail!("The GL display has no EGLDisplay")
+            };
+
+            let _ = self.functions.insert(Functions::load(&context)?);
+            self.egl_display = egl_display;
+        }
+
+        let size = gst_video::VideoInfo::from_caps(caps)?.size() as u32;
+        let pool = gst_gl::GLBufferPool::new(&context);
+        let mut config = pool.config();
+        config.set_params(Some(caps), size, 0, 0);
+        config.add_option(gst_video::BUFFER_POOL_OPTION_VIDEO_META);
+        config.add_option(gst_gl::BUFFER_POOL_OPTION_GL_SYNC_META);
+        pool.set_config(config)?;
+
+        let _ = self.context.insert(context);
+
+        Ok((pool.upcast(), size))
+    }
+
+    // Copies the x, y, width, height part of the pixmap into buffer's texture
+    // The pixmap is bound fresh each time, so the copy sees whatever the server last drew
+    pub fn blit(&self, buffer: &mut gst::BufferRef, pixmap: u32, source: [i32; 4]) -> Result<()> {
+        let (context, functions) = match (self.context.as_ref(), self.functions) {
+            (Some(context), Some(functions)) => (context, functions),
+            _ => bail!("GL output isn't set up")
+        };
+
+        unsafe {
+            // Mapped for GL the data is the texture name, and writing marks any CPU copy as outdated
+            let mut map = mem::MaybeUninit::<gst::ffi::GstMapInfo>::zeroed();
+            if gst::ffi::gst_buffer_map(buffer.as_mut_ptr(), map.as_mut_ptr(), gst::ffi::GST_MAP_WRITE | gst_gl::ffi::GST_MAP_GL as u32) == glib::ffi::GFALSE {
+                bail!("Failed to map GL memory");
+            }
+            let mut map = map.assume_init();
+
+            let mut blit = Blit {
+                functions,
+                egl_display: self.egl_display,
+                pixmap,
+                source,
+                texture: *(map.data as *const u32),
+                result: Ok(())
+            };
+
+            // Blocks until the GL thread has run it
+            gst_gl::ffi::gst_gl_context_thread_add(context.to_glib_none().0, Some(run_blit), &mut blit as *mut Blit as glib::ffi::gpointer);
+            gst::ffi::gst_buffer_unmap(buffer.as_mut_ptr(), &mut map);
+
+            blit.result?;
+        }
+
+        // Consumers wait on this before sampling the texture from their own context
+        if buffer.meta::<gst_gl::GLSyncMeta>().is_none() {
+            gst_gl::GLSyncMeta::add(buffer, context);
+        }
+        buffer.meta::<gst_gl::GLSyncMeta>().unwrap().set_sync_point(context);
+
+        Ok(())
+    }
+
+    // The context belongs to the stream, the display and application context are kept for the next one
+    pub fn stop(&mut self) {
+        self.context.take();
+        self.functions.take();
+        self.egl_display = 0;
+        self.negotiated = false;
+    }
+}
+
+struct Blit {
+    functions: Functions,
+    egl_display: usize,
+    pixmap: u32,
+    source: [i32; 4],
+    texture: u32,
+    result: Result<()>
+}
+
+impl Blit {
+    // Runs on the GL thread with the context current
+    unsafe fn run(&self) -> Result<()> {
+        let f = &self.functions;
+        let egl_display = self.egl_display as *mut c_void;
+
+        let attributes = [EGL_IMAGE_PRESERVED_KHR, EGL_TRUE, EGL_NONE];
+        let image = (f.create_image)(egl_display, ptr::null_mut(), EGL_NATIVE_PIXMAP_KHR, self.pixmap as usize as *mut c_void, attributes.as_ptr());
+        if image.is_null() {
+            bail!("eglCreateImageKHR failed for pixmap {:#x}, the GL display has to be on the captured X server", self.pixmap);
+        }
+
+        let mut texture = 0;
+        (f.gen_textures)(1, &mut texture);
+        (f.bind_texture)(GL_TEXTURE_2D, texture);
+        (f.image_target_texture)(GL_TEXTURE_2D, image);
+        (f.bind_texture)(GL_TEXTURE_2D, 0);
+
+        let mut framebuffers = [0; 2];
+        (f.gen_framebuffers)(2, framebuffers.as_mut_ptr());
+        (f.bind_framebuffer)(GL_READ_FRAMEBUFFER, framebuffers[0]);
+        (f.framebuffer_texture_2d)(GL_READ_FRAMEBUFFER, GL_COLOR_ATTACHMENT0, GL_TEXTURE_2D, texture, 0);
+        (f.bind_framebuffer)(GL_DRAW_FRAMEBUFFER, framebuffers[1]);
+        (f.framebuffer_texture_2d)(GL_DRAW_FRAMEBUFFER, GL_COLOR_ATTACHMENT0, GL_TEXTURE_2D, self.texture, 0);
+
+        let complete = (f.check_framebuffer_status)(GL_READ_FRAMEBUFFER) == GL_FRAMEBUFFER_COMPLETE
+            && (f.check_framebuffer_status)(GL_DRAW_FRAMEBUFFER) == GL_FRAMEBUFFER_COMPLETE;
+
+        // Both the pixmap and GLMemory keep the top row first, so nothing is flipped
+        if complete {
+            let [x, y, width, height] = self.source;
+            (f.blit_framebuffer)(x, y, x + width, y + height, 0, 0, width, height, GL_COLOR_BUFFER_BIT, GL_NEAREST);
+        }
+
+        (f.bind_framebuffer)(GL_FRAMEBUFFER, 0);
+        (f.delete_framebuffers)(2, framebuffers.as_ptr());
+        (f.delete_textures)(1, &texture);
+        (f.destroy_image)(egl_display, image);
+
+        if !complete {
+            bail!("The pixmap's texture can't be attached to a framebuffer");
+        }
+
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn run_blit(_context: *mut gst_gl::ffi::GstGLContext, data: glib::ffi::gpointer) {
+    let blit = &mut *(data as *mut Blit);
+    blit.result = blit.run();
+}
+
+// Asks downstream, then the application, for a context_type context, either way it arrives through set_context
+// found is checked before each step, so nothing is asked once the element has one
+pub fn query_context(element: &gst::Element, context_type: &str, found: impl Fn() -> bool) {
+    if found() {
+        return;
+    }
+
+    let mut query = gst::query::Context::new(context_type);
+    if element.static_pad("src").is_some_and(|pad| pad.peer_query(&mut query)) {
+        if let Some(context) = query.context_owned() {
+            element.set_context(&context);
+        }
+    }
+
+    if found() {
+        return;
+    }
+
+    // Bus sync handlers answer this before post_message returns
+    let _ = element.post_message(gst::message::NeedContext::builder(context_type).src(element).build());
+}
+
+// Same search as gst_gl_ensure_element_data, a new display is made and announced when nobody has one
+pub fn ensure_display(element: &gst::Element, found: impl Fn() -> bool) {
+    query_context(element, gst_gl::GL_DISPLAY_CONTEXT_TYPE, &found);
+    if found() {
+        return;
+    }
+
+    let display = gst_gl::GLDisplay::new();
+    let context = gst::Context::new(gst_gl::GL_DISPLAY_CONTEXT_TYPE, true);
+    context.set_gl_display(&display);
+
+    element.set_context(&context);
+    let _ = element.post_message(gst::message::HaveContext::builder(context).src(element).build());
+}
+
+// local is a context downstream renders with, reusing it saves a copy between contexts
+pub fn local_context(element: &gst::Element) -> Option<gst_gl::GLContext> {
+    unsafe {
+        let mut context = ptr::null_mut();
+        if gst_gl::ffi::gst_gl_query_local_gl_context(element.to_glib_none().0, gst::ffi::GST_PAD_SRC, &mut context) == glib::ffi::GFALSE {
+            return None;
+        }
+
+        from_glib_full(context)
+    }
+}
+
+// GLMemory version of caps' first structure ahead of caps, so it's picked when downstream takes both
+pub fn with_memory_caps(caps: gst::Caps) -> gst::Caps {
+    let mut structure = match caps.structure(0) {
+        Some(structure) => structure.to_owned(),
+        None => return caps
+    };
+
+    structure.set("format", gst_video::VideoFormat::Rgba.to_str());
+    structure.set("texture-target", "2D");
+    structure.remove_field("colorimetry");
+
+    let mut gl_caps = gst::Caps::builder_full()
+        .structure_with_features(structure, gst_gl::CAPS_FEATURES_MEMORY_GL_MEMORY.clone())
+        .build();
+    gl_caps.get_mut().unwrap().append(caps);
+
+    gl_caps
+}
+
+pub fn is_memory_caps(caps: &gst::CapsRef) -> bool {
+    caps.features(0).is_some_and(|features| features.contains(gst_gl::CAPS_FEATURE_MEMORY_GL_MEMORY))
+}
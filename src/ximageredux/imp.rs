@@ -1,20 +1,33 @@
-use std::{sync::{Mutex, atomic::{AtomicBool, Ordering}, Arc, MutexGuard}, time::Duration, ffi::CStr, thread::{JoinHandle, self}, convert::TryInto};
+use std::{sync::{Mutex, Condvar, atomic::{AtomicBool, Ordering}, Arc, MutexGuard}, time::{Duration, Instant}, thread::{JoinHandle, self}};
 
 use derivative::Derivative;
-use gst::{glib::{self, ffi::{G_LITTLE_ENDIAN, G_BIG_ENDIAN}}, subclass::prelude::{ObjectSubclass, ElementImpl, ObjectImpl, GstObjectImpl, ObjectImplExt, ObjectSubclassExt}, prelude::{ToValue, ElementExtManual, ParamSpecBuilderExt, StaticType, ObjectExt}, FlowError, error_msg};
-use gst_app::prelude::BaseSrcExt;
+use gst::{glib::{self, ffi::{G_LITTLE_ENDIAN, G_BIG_ENDIAN}, translate::from_glib}, subclass::prelude::{ObjectSubclass, ElementImpl, ElementImplExt, ObjectImpl, GstObjectImpl, ObjectImplExt, ObjectSubclassExt, ObjectSubclassIsExt}, prelude::{ToValue, ToSendValue, ClockExt, ClockExtManual, ElementExt, ElementExtManual, PadExtManual, ParamSpecBuilderExt, GstParamSpecBuilderExt, StaticType, ObjectExt}, FlowError, error_msg};
+use gst_app::prelude::{BaseSrcExt, BaseSrcExtManual};
 use gst_base::{subclass::{prelude::{BaseSrcImpl, BaseSrcImplExt, PushSrcImpl}, base_src::CreateSuccess}, PushSrc};
-use gst_video::ffi::{gst_video_format_from_masks, gst_video_format_to_string};
+use gst_video::ffi::gst_video_format_from_masks;
 use once_cell::sync::Lazy;
 use anyhow::{Result, bail};
-use xcb::{x::{GetGeometry, Drawable, GetImage, self, ImageOrder, ChangeWindowAttributes, Cw, EventMask, QueryPointer, GetProperty}, CookieWithReplyChecked, Connection};
+use xcb::{x::{GetGeometry, GetWindowAttributes, Drawable, GetImage, self, ImageOrder, ChangeWindowAttributes, Cw, EventMask, QueryPointer, TranslateCoordinates}, CookieWithReplyChecked, Connection, Xid as _};
 use xcb::x::Event::ConfigureNotify;
 use std::convert::TryFrom;
-use xcb::x::Event::PropertyNotify;
+use std::{io::Write, os::unix::{io::AsRawFd, net::UnixStream}, path::PathBuf, sync::mpsc};
+use xcb::x::Event::{PropertyNotify, DestroyNotify, MapNotify};
 
-use gst::{error, trace};
+use gst::{debug, error, info, trace, warning};
 
-use crate::WindowVisibility;
+use crate::{WindowVisibility, CursorFallback, CursorMode, WindowType, CropCoordinates, TimestampMode, CaptureBackend, BackingStore, OutputFormat, Colorimetry, NameMatch, ToplevelMode};
+
+use super::cursor::{self, CursorImage};
+#[cfg(feature = "gl")]
+use super::gl;
+#[cfg(feature = "gl")]
+use gst::prelude::{BufferPoolExtManual, Cast};
+use super::monitor;
+use super::shm;
+use super::thumbnail;
+use super::window::{self, Atoms, FrameExtents};
+use super::xauth;
+use super::yuv;
 
 pub static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -26,24 +39,279 @@ pub static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
 
 type Xid = u32;
 
+// Upper bound on how long the watcher sleeps without checking its run flag
+const WATCHER_POLL_TIMEOUT_MS: i32 = 500;
+
+// A watcher silent for this long is assumed dead and replaced, it normally wakes at least every poll timeout
+const WATCHER_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Past this many damaged rectangles a single bounding box is grabbed instead
+const MAX_DAMAGE_REGIONS: usize = 16;
+
+// How long select-on-start waits for a click before start fails
+const SELECT_ON_START_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How often the last frame is refreshed while paused with capture-when-paused
+const PAUSED_CAPTURE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Context type used to share one X connection between elements
+const CONNECTION_CONTEXT_TYPE: &str = "gst.ximageredux.x11.connection";
+
+// Shared by the State default and the property default so they can't diverge
+const DEFAULT_SHOW_CURSOR: bool = true;
+
+// DPI the cursor theme's default size is designed for, auto cursor-scale is relative to it
+const BASE_DPI: f64 = 96.0;
+
+// How long the highlight-clicks ring takes to fade out
+const CLICK_HIGHLIGHT_DURATION: Duration = Duration::from_millis(400);
+
+// Size of the blank frames pushed while wait-for-window has nothing to capture yet
+const WAITING_FRAME_SIZE: (u16, u16) = (640, 480);
+
+// Equivalent of 120fps, protects the X server from runaway pipelines
+const DEFAULT_MIN_FRAME_INTERVAL_MS: u32 = 8;
+
 #[derive(Derivative)]
 #[derivative(Default)]
 struct State {
-    connection: Option<xcb::Connection>,
+    // Shared with other elements through a GstContext
+    connection: Option<Arc<xcb::Connection>>,
+    // Connection from a context set on the element, used instead of opening one
+    context_connection: Option<SharedConnection>,
     screen_num: Option<i32>,
     xid: Option<Xid>,
-    // #[derivative(Default(value="true"))]
+    // Set when xid was found by resolve_window rather than set by the user, so it's resolved again on restart
+    xid_resolved: bool,
+    // Set by the watcher when the window goes away
+    window_destroyed: bool,
+    window_type: WindowType,
+    // Matched against the title and WM_CLASS when resolving the window
+    window_name: Option<String>,
+    name_match: NameMatch,
+    // Either half of WM_CLASS, or "instance,class" for both like xprop prints them
+    window_class: Option<String>,
+    // _NET_WM_PID to match, 0 for any
+    window_pid: u32,
+    // Let the user click the window to capture when no xid is set, like xwininfo
+    select_on_start: bool,
+    // Written to by unlock to cancel a select-on-start click that's still pending
+    select_wake: Option<UnixStream>,
+    // Retarget capture to whichever window _NET_ACTIVE_WINDOW points at
+    follow_active_window: bool,
+    // Set by the watcher when _NET_ACTIVE_WINDOW changes
+    active_window_changed: bool,
+    // xid set while streaming, create switches to it before the next frame
+    pending_xid: Option<Xid>,
+    // X display to connect to instead of DISPLAY, e.g. ":1" for a nested server
+    display: Option<String>,
+    // Cookie file for displays owned by another user or session, instead of XAUTHORITY
+    xauthority: Option<PathBuf>,
+    // Screen of the display to use, -1 for the display's default screen
+    #[derivative(Default(value = "-1"))]
+    screen: i32,
+    // Capture the whole screen through its root window when xid isn't set
+    capture_root: bool,
+    // RandR output to crop root captures to, by name or by index
+    monitor_name: Option<String>,
+    #[derivative(Default(value = "-1"))]
+    monitor_index: i32,
+    // Geometry of the selected output in root coordinates
+    monitor: Option<Region>,
+    // Find an explicit xid's window again by class and PID after it's destroyed
+    reattach: bool,
+    // What the explicitly set window was, recorded when binding with reattach
+    bound_identity: Option<WindowIdentity>,
+    // Set while looking for the recreated window
+    reattach_identity: Option<WindowIdentity>,
+    // The next bind announces window-found even though xid was set explicitly, e.g. after waiting for it
+    window_found_pending: bool,
+    toplevel_mode: ToplevelMode,
+    // Start without the window and push blank frames until it appears
+    wait_for_window: bool,
+    // Open while waiting for the window, receives the root's CreateNotify, MapNotify and PropertyNotify
+    root_watch: Option<Connection>,
+    #[derivative(Default(value="DEFAULT_SHOW_CURSOR"))]
     show_cursor: bool,
     #[derivative(Default(value="true"))]
     needs_size_update: bool,
+    // Visibility, fullscreen, and title, which only change with properties
+    #[derivative(Default(value="true"))]
+    needs_state_update: bool,
     position: Option<Position>,
     size: Option<Size>,
+    crop_x: i32,
+    crop_y: i32,
+    // 0 extends the crop to the window edge
+    crop_width: u32,
+    crop_height: u32,
+    crop_coordinates: CropCoordinates,
+    // Crop off the window manager's decorations when capturing a frame window
+    crop_decorations: bool,
+    frame_extents: Option<FrameExtents>,
+    // Crop off the transparent shadows client-side decorated windows draw around themselves
+    #[derivative(Default(value = "true"))]
+    crop_csd_shadows: bool,
+    csd_extents: Option<FrameExtents>,
+    // Part of the window that's captured, in window coordinates
+    region: Option<Region>,
+    region_clamped: bool,
+    // Strip height the server could allocate after a BadAlloc/BadLength, until the region changes
+    max_strip_rows: Option<u16>,
     frame_duration: Duration,
+    // Running time the last fresh frame was due at
     last_frame_time: Option<gst::ClockTime>,
+    // Last PTS pushed, later buffers are kept strictly after it
+    last_pts: Option<gst::ClockTime>,
+    // Gap between the last two PTSs, the duration of frames at a variable framerate
+    last_pts_delta: Option<gst::ClockTime>,
+    timestamp_mode: TimestampMode,
+    // Latest X server timestamp seen by the watcher and when it was seen, in milliseconds
+    x_server_time: Option<(u32, Instant)>,
     resize_run: Option<Arc<AtomicBool>>,
     resize_handle: Option<JoinHandle<()>>,
+    resize_wake: Option<UnixStream>,
+    // Last time the watcher went around its loop
+    watcher_heartbeat: Option<Instant>,
+    watcher_restarts: u32,
     last_frame: Option<gst::Buffer>,
-    visibility: WindowVisibility
+    visibility: WindowVisibility,
+    fullscreen: bool,
+    title: Option<String>,
+    // Title as the window set it, for applications handling legacy encodings themselves
+    title_raw: Option<Vec<u8>>,
+    atoms: Option<Atoms>,
+    cursor_fallback: CursorFallback,
+    cursor_mode: CursorMode,
+    // Drawn instead of the real cursor, loaded from cursor-image or cursor-image-location on start
+    cursor_image: Option<gst::Sample>,
+    cursor_image_location: Option<PathBuf>,
+    cursor_hotspot_x: u32,
+    cursor_hotspot_y: u32,
+    custom_cursor: Option<CursorImage>,
+    // 0 scales by the detected DPI, which is worked out when the connection opens
+    #[derivative(Default(value="1.0"))]
+    cursor_scale: f64,
+    #[derivative(Default(value="1.0"))]
+    cursor_auto_scale: f64,
+    // Attach the cursor as overlay composition meta instead of drawing it into frames
+    cursor_overlay: bool,
+    // Draw a fading ring around the pointer after each click, the watcher records when the last one happened
+    highlight_clicks: bool,
+    // Attach the pointer's position and buttons to every buffer, even with the cursor hidden
+    pointer_meta: bool,
+    last_click: Option<Instant>,
+    cursor_only_when_moving: bool,
+    last_cursor_position: Option<Position>,
+    // Frame the cursor was last drawn onto, before drawing
+    last_cursor_source: Option<gst::Buffer>,
+    cached_cursor: Option<CursorImage>,
+    // Last shape from GetCursorImage, reused until the watcher sees a CursorNotify
+    cursor_shape: Option<CursorImage>,
+    // Set by the watcher while it receives cursor change notifications
+    cursor_tracking: bool,
+    xfixes_available: bool,
+    // Format frames are captured in, differs from the caps when converting to YUV
+    video_info: Option<gst_video::VideoInfo>,
+    // Negotiated YUV format frames are converted to, if any
+    output_info: Option<gst_video::VideoInfo>,
+    output_format: OutputFormat,
+    colorimetry: Colorimetry,
+    native_format: Option<gst_video::VideoFormat>,
+    pixmap_format: Option<PixmapFormat>,
+    // Depth the negotiated format was computed for
+    depth: Option<u8>,
+    measure_latency: bool,
+    log_x_errors: bool,
+    benchmark: bool,
+    benchmark_window: BenchmarkWindow,
+    // Last completed benchmark window
+    stats: Option<gst::Structure>,
+    repeat_last_frame_on_eos: bool,
+    #[derivative(Default(value="true"))]
+    flush_on_resize: bool,
+    capture_when_paused: bool,
+    still_image: bool,
+    still_frame_sent: bool,
+    paused_capture_stop: Option<mpsc::Sender<()>>,
+    paused_capture_handle: Option<JoinHandle<()>>,
+    eos_requested: bool,
+    stop_on_hidden: bool,
+    // Whether the size and visibility were announced after the first negotiation
+    initial_state_announced: bool,
+    final_frame_sent: bool,
+    redirect_subwindows: bool,
+    // Read frames from the window's Composite pixmap, so covered or minimized windows show their own content
+    offscreen: bool,
+    window_redirected: bool,
+    // Named pixmap and the window's border width, the pixmap includes the border
+    offscreen_pixmap: Option<(x::Pixmap, i16)>,
+    // The window was mapped or resized, which gives it a new pixmap
+    offscreen_stale: bool,
+    composite_available: bool,
+    xtest_available: bool,
+    xinput_available: bool,
+    // XInput2 device whose pointer is tracked, -1 for the core pointer
+    #[derivative(Default(value="-1"))]
+    pointer_device: i32,
+    randr_available: bool,
+    shm_available: bool,
+    #[derivative(Default(value="true"))]
+    use_shm: bool,
+    // Sized for the capture region, reallocated when the region's size changes
+    shm_segment: Option<shm::Segment>,
+    // Framebuffer pixels per physical pixel on the output the window is on
+    #[derivative(Default(value="1.0"))]
+    scale_factor: f64,
+    subwindows_redirected: bool,
+    backing_store: BackingStore,
+    // The window's own backing-store attribute, restored when capture stops
+    original_backing_store: Option<x::BackingStore>,
+    #[derivative(Default(value="Duration::from_millis(DEFAULT_MIN_FRAME_INTERVAL_MS as u64)"))]
+    min_frame_interval: Duration,
+    frame_repeat_limit: u32,
+    // Row alignment in bytes for captured frames, 0 keeps the X server's padding
+    align: u32,
+    #[cfg(feature = "test-inject")]
+    injected_frames: std::collections::VecDeque<gst::Buffer>,
+    // Set once a frame is injected, from then on X is never used
+    #[cfg(feature = "test-inject")]
+    injected_size: Option<Size>,
+    consecutive_fallbacks: u32,
+    #[derivative(Default(value="gst::Fraction::new(1, 1)"))]
+    pixel_aspect_ratio: gst::Fraction,
+    capture_backend: CaptureBackend,
+    // Set by the watcher once it's listening for Present completions
+    present_active: bool,
+    // Flag raised by the watcher whenever the window presents a frame
+    present_frame: Arc<(Mutex<bool>, Condvar)>,
+    // Static windows reuse the last capture instead of paying for a GetImage every frame
+    #[derivative(Default(value="true"))]
+    use_damage: bool,
+    damage_regions: bool,
+    // Set by the watcher once a Damage object exists for the window
+    damage_active: bool,
+    #[derivative(Default(value="true"))]
+    needs_content_update: bool,
+    // Forces a whole-window grab regardless of damage, e.g. after a resize
+    #[derivative(Default(value="true"))]
+    damage_full: bool,
+    damage_rects: Vec<x::Rectangle>,
+    // Attach what changed since the previous frame as "damage" region of interest metas
+    damage_meta: bool,
+    // Changed rectangles of the frame get_frame last produced, in window coordinates
+    frame_damage: Option<Vec<x::Rectangle>>,
+    // Last capture before cursor compositing, damage regions are patched into this
+    last_capture: Option<gst::Buffer>,
+    last_create_instant: Option<Instant>,
+    // Pacing wait in progress, unlock unschedules it so flushes and state changes don't sit it out
+    clock_wait: Option<gst::SingleShotClockId>,
+    // Set between unlock and unlock_stop, no new pacing wait is started
+    unlocked: bool,
+    // Whether the last create pushed a new capture rather than reusing last_frame
+    last_frame_was_fresh: bool,
+    #[cfg(feature = "gl")]
+    gl: gl::Gl
 }
 
 #[derive(Default)]
@@ -51,13 +319,63 @@ pub struct XImageRedux {
     state: Arc<Mutex<State>>
 }
 
+// Connection and its default screen, stored in CONNECTION_CONTEXT_TYPE contexts
+// The display it was opened for keeps elements capturing different displays from sharing it
+#[derive(Clone, glib::Boxed)]
+#[boxed_type(name = "GstXImageReduxConnection")]
+struct SharedConnection {
+    connection: Arc<xcb::Connection>,
+    screen_num: i32,
+    display: Option<String>
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
 struct Size {
     width: u16,
     height: u16
 }
 
-#[derive(Debug, PartialEq, Eq)]
+// How a recreated window is recognised, None fields match anything
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+struct WindowIdentity {
+    class: Option<(String, String)>,
+    pid: Option<u32>
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Region {
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16
+}
+
+// Bits per pixel and scanline padding of the window's depth, used to size GetImage replies
+#[derive(Debug, Clone, Copy)]
+struct PixmapFormat {
+    bits_per_pixel: u8,
+    scanline_pad: u8
+}
+
+// Counters for the benchmark window in progress
+#[derive(Debug, Default)]
+struct BenchmarkWindow {
+    start: Option<Instant>,
+    frames: u32,
+    // Frames served from last_frame instead of a new capture
+    repeated: u32,
+    round_trips: u32,
+    round_trip_total: Duration
+}
+
+enum DamageUpdate {
+    // Nothing changed, the last capture can be reused as is
+    Unchanged(gst::Buffer),
+    Regions(Vec<x::Rectangle>),
+    Full
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 struct Position {
     x: i16,
     y: i16
@@ -77,313 +395,3484 @@ impl XImageRedux {
     fn get_frame(&self) -> Result<gst::Buffer> {
         self.update_size_if_needed()?;
 
-        let state = self.state.lock().unwrap();
-        let (conn, xid) = get_connection(&state)?;
+        if std::mem::take(&mut self.state.lock().unwrap().offscreen_stale) {
+            if let Err(e) = self.name_offscreen_pixmap() {
+                warning!(CAT, "Failed to capture offscreen, capturing what's on screen instead: {}", e);
+            }
+        }
 
-        let cookie = conn.send_request(&GetImage {
-            format: x::ImageFormat::ZPixmap, // jpg
-            drawable: xcb::x::Drawable::Window(unsafe { xcb::XidNew::new(xid) }),
-            x: 0,
-            y: 0,
-            width: state.size.as_ref().unwrap().width,
-            height: state.size.as_ref().unwrap().height,
-            plane_mask: u32::MAX,
-        });
+        #[cfg(feature = "gl")]
+        if self.state.lock().unwrap().gl.negotiated {
+            return self.get_gl_frame();
+        }
 
-        let reply = wait_for_reply(conn, cookie)?;
+        match self.take_damage() {
+            DamageUpdate::Unchanged(buf) => return Ok(buf),
+            DamageUpdate::Regions(rects) => match self.patch_regions(&rects) {
+                Ok(buf) => return Ok(buf),
+                Err(e) => trace!(CAT, "Failed to grab damaged regions, grabbing whole window: {}", e)
+            },
+            DamageUpdate::Full => {}
+        }
 
-        let mut buf = gst::Buffer::from_slice(reply.data().to_owned());
-        let buf = buf.make_mut();
-        buf.set_duration(gst::ClockTime::from_mseconds(state.frame_duration.as_millis() as u64));
+        let mut data = match self.get_image() {
+            Ok(data) => data,
+            Err(e) if is_x_error(&e, |e| matches!(e, x::Error::Match(_))) => {
+                // Window shrank between the size update and the capture, retry once at the current size
+                trace!(CAT, "GetImage size mismatch, retrying with fresh geometry");
+                self.state.lock().unwrap().needs_size_update = true;
 
-        Ok(buf.to_owned())
-    }
+                if self.update_size_if_needed()? {
+                    if let Err(e) = self.negotiate() {
+                        bail!("Failed to renegotiate after resize: {}", e);
+                    }
+                }
 
-    // Function looks weird to get around mutex issues
-    // Returns whether size was updated
-    fn update_size_if_needed(&self) -> Result<bool> {
-        let should_update = {
-            let mut state = self.state.lock().unwrap();
+                self.get_image()?
+            }
+            Err(e) if is_x_error(&e, |e| matches!(e, x::Error::Alloc(_) | x::Error::Length(_))) => {
+                // The server couldn't allocate the image, halve the strips until it can
+                loop {
+                    let rows = {
+                        let mut state = self.state.lock().unwrap();
+                        let current = state.max_strip_rows.or(state.region.map(|region| region.height)).unwrap_or(1);
+                        if current <= 1 {
+                            state.damage_full = true;
+                            return Err(e);
+                        }
 
-            if state.needs_size_update || state.size.is_none() {
-                state.needs_size_update = false;
-                true
-            } else {
-                false
+                        *state.max_strip_rows.insert(current / 2)
+                    };
+
+                    trace!(CAT, "GetImage failed to allocate, retrying with {}-row strips", rows);
+
+                    match self.get_image() {
+                        Ok(data) => break data,
+                        Err(e) if is_x_error(&e, |e| matches!(e, x::Error::Alloc(_) | x::Error::Length(_))) => continue,
+                        Err(e) => {
+                            self.state.lock().unwrap().damage_full = true;
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                self.state.lock().unwrap().damage_full = true;
+                return Err(e);
             }
         };
 
-        if should_update {
-            let new = self.get_size()?;
-            let old_size = self.state.lock().unwrap().size;
+        let mut state = self.state.lock().unwrap();
 
-            if old_size.is_none() || old_size.unwrap() != new {
-                if old_size.is_none() || new.width != old_size.unwrap().width {
-                    self.obj().set_property("width", new.width as u32);
-                }
-                if old_size.is_none() || new.height != old_size.unwrap().height {
-                    self.obj().set_property("height", new.height as u32);
-                }
+        if let Some(info) = alpha_fill_format(&state) {
+            fill_alpha(&mut data, &info);
+        }
 
-                self.obj().emit_by_name::<()>("resize", &[&(new.width as u32), &(new.height as u32)]);
+        // A single contiguous memory with its real stride in a GstVideoMeta, so appsink can map it without copying
+        let buf = match (state.align, state.video_info.as_ref()) {
+            (align, Some(info)) if align > 0 && info.format_info().n_planes() == 1 => align_rows(&data, info, align as usize)?,
+            (_, Some(info)) if info.format_info().n_planes() == 1 && info.height() > 0 => {
+                let stride = data.len() / info.height() as usize;
+                let mut buf = gst::Buffer::from_mut_slice(data);
+                add_video_meta(buf.get_mut().unwrap(), info, stride)?;
+                buf
             }
+            _ => gst::Buffer::from_mut_slice(data)
+        };
 
-            let _ = self.state.lock().unwrap().size.insert(new);
+        state.damage_full = false;
+        let _ = state.last_capture.insert(buf.clone());
 
-            let new = self.get_window_visibility()?;
-            if new != self.state.lock().unwrap().visibility {
-                self.state.lock().unwrap().visibility = new;
-                self.obj().set_property("visibility", new);
-            }
+        Ok(buf)
+    }
+
+    // Copies the offscreen pixmap into a texture from the negotiated pool, damage only decides whether a copy is needed
+    #[cfg(feature = "gl")]
+    fn get_gl_frame(&self) -> Result<gst::Buffer> {
+        if let DamageUpdate::Unchanged(buf) = self.take_damage() {
+            return Ok(buf);
         }
 
-        Ok(should_update)
-    }
+        let pool = match self.obj().buffer_pool() {
+            Some(pool) => pool,
+            None => bail!("No buffer pool to take textures from")
+        };
+        let mut buf = pool.acquire_buffer(None)?;
 
-    fn get_size(&self) -> Result<Size> {
         let mut state = self.state.lock().unwrap();
-        let (conn, xid) = get_connection(&state)?;
-        
-        let cookie = conn.send_request(&GetGeometry {
-            drawable: Drawable::Window(unsafe { xcb::XidNew::new(xid) })
-        });
+        let (pixmap, border, region) = match (state.offscreen_pixmap, state.region) {
+            (Some((pixmap, border)), Some(region)) => (pixmap, border, region),
+            (None, _) => bail!("GLMemory output needs the offscreen pixmap"),
+            _ => bail!("No size set!")
+        };
 
-        let reply = wait_for_reply(conn, cookie)?;
+        let source = [(region.x + border).into(), (region.y + border).into(), region.width.into(), region.height.into()];
+        if let Err(e) = state.gl.blit(buf.get_mut().unwrap(), pixmap.resource_id(), source) {
+            state.damage_full = true;
+            return Err(e);
+        }
 
-        let _ = state.position.insert(Position {
-            x: reply.x(),
-            y: reply.y()
-        });
+        state.damage_full = false;
+        let _ = state.last_capture.insert(buf.clone());
 
-        Ok(Size {
-            width: reply.width(),
-            height: reply.height()
-        })
+        Ok(buf)
     }
 
-    fn get_window_visibility(&self) -> Result<WindowVisibility> {
-        let state = self.state.lock().unwrap();
-        let (conn, xid) = get_connection(&state)?;
+    // Finds or makes the GL display and context, then a texture pool for caps and its buffer size
+    #[cfg(feature = "gl")]
+    fn prepare_gl(&self, caps: &gst::Caps) -> Result<(gst::BufferPool, u32)> {
+        let obj = self.obj();
+        let element = obj.upcast_ref::<gst::Element>();
 
-        let cookie = conn.send_request(&GetProperty {
-            delete: false,
-            window: unsafe { xcb::XidNew::new(xid) },
-            property: unsafe { xcb::XidNew::new(320) },
-            r#type: x::ATOM_ATOM,
-            long_offset: 0,
-            long_length: 4
-        });
+        // Not locked, whatever downstream or the application hands over comes back through set_context
+        gl::ensure_display(element, || self.state.lock().unwrap().gl.has_display());
+        gl::query_context(element, gl::APP_CONTEXT_TYPE, || self.state.lock().unwrap().gl.has_other_context());
+        let local = gl::local_context(element);
 
-        match conn.wait_for_reply(cookie) {
-            Ok(res) => {
-                if res.value::<u32>().iter().any(|v| *v == 324) { // Hide
-                    Ok(WindowVisibility::Hidden)
-                } else { // Show
-                    Ok(WindowVisibility::Visible)
-                }
-            }
-            Err(e) => {
-                bail!(e);
-            }
-        }
+        self.state.lock().unwrap().gl.prepare(local, caps)
     }
 
-    fn open_connection(&self) -> Result<()> {
+    // Decides how much of the window has to be grabbed based on accumulated damage
+    fn take_damage(&self) -> DamageUpdate {
         let mut state = self.state.lock().unwrap();
 
-        let (connection, screen_num) = match xcb::Connection::connect(None) {
-            Ok((c, s)) => (c, s),
-            Err(e) => bail!("Failed to connect to X11 server: {}", e.to_string())
+        let rects = std::mem::take(&mut state.damage_rects);
+        let needs_content_update = std::mem::replace(&mut state.needs_content_update, false);
+
+        let tracked = state.use_damage && state.damage_active && !state.damage_full;
+        let update = match state.last_capture.clone().filter(|_| tracked) {
+            None => DamageUpdate::Full,
+            Some(last) if !needs_content_update => DamageUpdate::Unchanged(last),
+            Some(_) => match (state.damage_regions, state.region) {
+                (true, Some(region)) if !rects.is_empty() => DamageUpdate::Regions(coalesce_damage(rects.clone(), region)),
+                _ => DamageUpdate::Full
+            }
         };
 
-        let _ = state.connection.insert(connection);
-        let _ = state.screen_num.insert(screen_num);
+        // Without a previous frame to compare against, everything counts as changed
+        state.frame_damage = Some(match (&update, state.region) {
+            (DamageUpdate::Unchanged(_), _) | (_, None) => Vec::new(),
+            (DamageUpdate::Regions(regions), _) => regions.clone(),
+            (DamageUpdate::Full, Some(region)) if tracked && state.last_capture.is_some() && !rects.is_empty() => coalesce_damage(rects, region),
+            (DamageUpdate::Full, Some(region)) => vec![x::Rectangle { x: region.x, y: region.y, width: region.width, height: region.height }]
+        });
 
-        Ok(())
+        update
     }
 
-    unsafe fn get_video_format(&self) -> Result<i32> {
-        let state = self.state.lock().unwrap();
-        let (conn, xid) = get_connection(&state)?;
+    // Grabs only the damaged rectangles and copies them into the last capture in place
+    fn patch_regions(&self, rects: &[x::Rectangle]) -> Result<gst::Buffer> {
+        let mut state = self.state.lock().unwrap();
 
-        let setup = conn.get_setup();
-        let mut endianness = match setup.bitmap_format_bit_order() {
-            ImageOrder::MsbFirst => G_BIG_ENDIAN,
-            ImageOrder::LsbFirst => G_LITTLE_ENDIAN
+        let info = match state.video_info.as_ref() {
+            Some(info) if info.format_info().n_planes() == 1 => info.clone(),
+            _ => bail!("Patching regions requires negotiated packed caps")
         };
+        let fill = alpha_fill_format(&state);
 
-        let cookie = conn.send_request(&GetGeometry {
-            drawable: Drawable::Window(xcb::XidNew::new(xid))
-        });
+        let mut buf = match state.last_capture.take() {
+            Some(buf) => buf,
+            None => bail!("No previous capture to patch")
+        };
 
-        let geometry_reply = wait_for_reply(conn, cookie)?;
+        let region = match state.region {
+            Some(region) => region,
+            None => bail!("No capture region set!")
+        };
+
+        let (conn, xid) = get_connection(&state)?;
+        let (drawable, offset) = capture_drawable(&state, xid);
 
-        let bpp = setup.pixmap_formats().iter().find(|fmt| fmt.depth() == geometry_reply.depth()).unwrap().bits_per_pixel();
+        // Send every request up front so the replies arrive in one round-trip
+        let cookies: Vec<_> = rects.iter().map(|rect| conn.send_request(&GetImage {
+            format: x::ImageFormat::ZPixmap,
+            drawable,
+            x: rect.x + offset,
+            y: rect.y + offset,
+            width: rect.width,
+            height: rect.height,
+            plane_mask: u32::MAX,
+        })).collect();
 
-        let screen = setup.roots().nth(state.screen_num.unwrap() as usize).unwrap();
+        let pixel_stride = info.format_info().pixel_stride()[0] as usize;
 
-        let visual = screen.allowed_depths()
-            .flat_map(|depth| depth.visuals().into_iter())
-            .find(|vis| vis.visual_id() == screen.root_visual())
-            .unwrap();
+        {
+            let buf = buf.make_mut();
+            let mut map = buf.map_writable()?;
+            let stride = map.len() / info.height() as usize;
 
-        // Our caps system handles 24/32bpp RGB as big-endian
-        let (red_mask, green_mask, blue_mask) = if (bpp == 24 || bpp == 32) && endianness == G_LITTLE_ENDIAN {
-            endianness = G_BIG_ENDIAN;
-            let mut set = (visual.red_mask().to_be(), visual.green_mask().to_be(), visual.blue_mask().to_be());
+            for (rect, cookie) in rects.iter().zip(cookies) {
+                let reply = wait_for_reply(conn, cookie)?;
+                let mut data = reply.data().to_owned();
+                if let Some(fill) = fill.as_ref() {
+                    fill_alpha(&mut data, fill);
+                }
 
-            if bpp == 24 {
-                set.0 >>= 8;
-                set.1 >>= 8;
-                set.2 >>= 8;
+                let src_stride = data.len() / rect.height as usize;
+                let row_len = rect.width as usize * pixel_stride;
+
+                for row in 0..rect.height as usize {
+                    let dst = ((rect.y - region.y) as usize + row) * stride + (rect.x - region.x) as usize * pixel_stride;
+                    let src = row * src_stride;
+                    map[dst..dst + row_len].copy_from_slice(&data[src..src + row_len]);
+                }
             }
+        }
 
-            set
-        } else {
-            (visual.red_mask(), visual.green_mask(), visual.blue_mask())
-        };
+        let _ = state.last_capture.insert(buf.clone());
 
-        let alpha_mask = if bpp == 32 {
-            !(red_mask | green_mask | blue_mask)
-        } else {
-            0
+        Ok(buf)
+    }
+
+    // Grabs the capture region at the currently known size
+    // Images larger than the server's maximum request length are grabbed in horizontal strips
+    fn get_image(&self) -> Result<Vec<u8>> {
+        let use_shm = {
+            let state = self.state.lock().unwrap();
+            state.use_shm && state.shm_available
         };
 
-        Ok(gst_video_format_from_masks(geometry_reply.depth().into(), bpp.into(), endianness, red_mask, green_mask, blue_mask, alpha_mask))
-    }
+        if use_shm {
+            match self.get_image_shm() {
+                Ok(data) => return Ok(data),
+                Err(e) => {
+                    warning!(CAT, "Failed to capture with MIT-SHM, falling back to GetImage: {}", e);
+                    let mut state = self.state.lock().unwrap();
+                    state.shm_available = false;
 
-    // Returns the relative position of the cursor in the window if it's in the window region
-    fn cursor_is_in_bounds(&self) -> Result<Option<Position>> {
-        let state = self.state.lock().unwrap();
+                    // Dropping only detaches locally, the server keeps it attached until told otherwise
+                    if let (Some(segment), Some(conn)) = (state.shm_segment.take(), state.connection.clone()) {
+                        if let Err(e) = segment.release(&conn) {
+                            warning!(CAT, "Failed to detach shared memory segment: {}", e);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
         let (conn, xid) = get_connection(&state)?;
-        let win = unsafe { xcb::XidNew::new(xid) };
 
-        if state.position.is_none() || state.size.is_none() {
-            bail!("No position/size set!");
+        let region = match state.region {
+            Some(region) => region,
+            None => bail!("No size set!")
+        };
+
+        let rows_per_strip = state.pixmap_format
+            .map(|fmt| rows_per_request(conn, fmt, region))
+            .unwrap_or(region.height)
+            .min(state.max_strip_rows.unwrap_or(u16::MAX));
+
+        if rows_per_strip < region.height {
+            trace!(CAT, "Image exceeds maximum request length, capturing in {}-row strips", rows_per_strip);
         }
 
-        let cookie = conn.send_request(&QueryPointer {
-            window: win
-        });
+        let capture_start = (state.measure_latency || state.benchmark).then(Instant::now);
 
-        let reply = wait_for_reply(conn, cookie)?;
+        let (drawable, offset) = capture_drawable(&state, xid);
+        let replies = get_image_strips(conn, drawable, Region { x: region.x + offset, y: region.y + offset, ..region }, rows_per_strip)?;
 
-        let position = state.position.as_ref().unwrap();
-        let size = state.size.as_ref().unwrap();
+        let round_trip = capture_start.map(|start| start.elapsed());
 
-        let bounds_match = reply.root_x() >= position.x && 
-            reply.root_y() >= position.y &&
-            reply.root_x() < position.x + i16::try_from(size.width).unwrap() && 
-            reply.root_y() < position.y + i16::try_from(size.height).unwrap();
+        let data = match replies.as_slice() {
+            [reply] => reply.data().to_owned(),
+            replies => replies.iter().flat_map(|reply| reply.data()).copied().collect()
+        };
 
-        Ok(if reply.same_screen() && bounds_match {
-            Some(Position {
-                x: reply.root_x() - position.x,
-                y: reply.root_y() - position.y,
-            })
-        } else { None })
-    }
-}
+        if let (Some(start), Some(round_trip)) = (capture_start, round_trip) {
+            if state.measure_latency {
+                debug!(CAT, "Capture latency: GetImage round-trip {:?}, copy {:?}", round_trip, start.elapsed() - round_trip);
+            }
 
-fn wait_for_reply<C>(conn: &Connection, cookie: C) -> Result<C::Reply> 
-    where C: CookieWithReplyChecked 
-    {
-        match conn.wait_for_reply(cookie) {
-            Ok(reply) => Ok(reply),
-            Err(e) => bail!("Failed to wait for X reply: {}", e)
+            if state.benchmark {
+                state.benchmark_window.round_trips += 1;
+                state.benchmark_window.round_trip_total += round_trip;
+            }
         }
-}
 
-fn get_connection<'a>(state: &'a MutexGuard<State>) -> Result<(&'a xcb::Connection, Xid)> {
-    let xid = match state.xid {
-        Some(xid) => xid,
-        None => bail!("XID is not set!"),
-    };
+        Ok(data)
+    }
 
-    Ok((state.connection.as_ref().unwrap(), xid))
-}
+    // Grabs the capture region into the shared segment, skipping the copy through the X socket
+    fn get_image_shm(&self) -> Result<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let (conn, xid) = match (state.connection.clone(), state.xid) {
+            (Some(conn), Some(xid)) => (conn, xid),
+            _ => bail!("Not connected!")
+        };
 
-#[glib::object_subclass]
-impl ObjectSubclass for XImageRedux {
-    const NAME: &'static str = "XImageRedux";
-    type Type = super::XImageRedux;
-    type ParentType = PushSrc;
-}
+        let (region, fmt) = match (state.region, state.pixmap_format) {
+            (Some(region), Some(fmt)) => (region, fmt),
+            _ => bail!("No size set!")
+        };
 
-impl PushSrcImpl for XImageRedux {
-    fn create(
-            &self,
-            _buffer: Option<&mut gst::BufferRef>,
-        ) -> Result<CreateSuccess, gst::FlowError> {
-        // Check if time for next frame
-        {
-            let mut state = self.state.lock().unwrap();
-            if let Some(last_time) = state.last_frame_time {
-                if gst::ClockTime::default() - last_time >= gst::ClockTime::from_mseconds(state.frame_duration.as_millis().try_into().unwrap()) {
-                    // Time for new frame
-                    let _ = state.last_frame_time.insert(gst::ClockTime::default());
-                } else if let Some(buf) = state.last_frame.as_ref() {
-                    // Not time for new frame yet, use last one if it exists
-                    return Ok(CreateSuccess::NewBuffer(buf.clone()));
+        let pad = fmt.scanline_pad.max(8) as usize;
+        let size = (region.width as usize * fmt.bits_per_pixel as usize).div_ceil(pad) * pad / 8 * region.height as usize;
+
+        if state.shm_segment.as_ref().is_none_or(|segment| segment.size() != size) {
+            if let Some(old) = state.shm_segment.take() {
+                if let Err(e) = old.release(&conn) {
+                    warning!(CAT, "Failed to detach old shared memory segment: {}", e);
                 }
             }
+
+            debug!(CAT, "Allocating {} byte shared memory segment", size);
+            let _ = state.shm_segment.insert(shm::Segment::new(&conn, size)?);
         }
-        
-        // Updates size
-        match self.update_size_if_needed() {
-            Ok(did_update_size) => if did_update_size {
-                if let Err(e) = self.negotiate() {
-                    error!(CAT, "Failed to renegotiate after resize: {}", e.to_string());
-                    return Err(gst::FlowError::Error);
-                }
+
+        let capture_start = (state.measure_latency || state.benchmark).then(Instant::now);
+
+        let (drawable, offset) = capture_drawable(&state, xid);
+        let segment = state.shm_segment.as_ref().unwrap();
+        let cookie = conn.send_request(&xcb::shm::GetImage {
+            drawable,
+            x: region.x + offset,
+            y: region.y + offset,
+            width: region.width,
+            height: region.height,
+            plane_mask: u32::MAX,
+            format: x::ImageFormat::ZPixmap as u8,
+            shmseg: segment.seg(),
+            offset: 0
+        });
+
+        let reply = wait_for_reply(&conn, cookie)?;
+        let round_trip = capture_start.map(|start| start.elapsed());
+
+        let data = segment.data()[..(reply.size() as usize).min(size)].to_vec();
+
+        if let (Some(start), Some(round_trip)) = (capture_start, round_trip) {
+            if state.measure_latency {
+                debug!(CAT, "Capture latency: ShmGetImage round-trip {:?}, copy {:?}", round_trip, start.elapsed() - round_trip);
             }
-            Err(e) => {
-                error!(CAT, "Failed to resize: {}", e.to_string());
-                return Err(gst::FlowError::Error);
+
+            if state.benchmark {
+                state.benchmark_window.round_trips += 1;
+                state.benchmark_window.round_trip_total += round_trip;
             }
         }
 
-        // Get a frame
-        let frame = match self.get_frame() {
-            Ok(f) => f,
-            Err(e) => {
-                // If failed to get frame, try to use the last one as a temporary measure
-                if let Some(buf) = &self.state.lock().unwrap().last_frame {
-                    trace!(CAT, "Failed to get frame, but last frame is usable.");
-                    return Ok(CreateSuccess::NewBuffer(buf.clone()));
-                } else {
-                    error!(CAT, "Failed to get frame: {}", e.to_string());
-                    return Err(FlowError::Error);
-                }
-            }
-        };
+        Ok(data)
+    }
 
-        // Copy cursor in if needed
-        if self.state.lock().unwrap().show_cursor {
-            match self.cursor_is_in_bounds() {
-                Ok(res) => if let Some(_pos) = res {
-                    // Trying to get the cursor image causes a crash for some reason so it's disabled for now
-                    // Once implemented, set default for show-cursor to true in State struct
-                    todo!()
-                    
-                    // let state = self.state.lock().unwrap();
-                    // let (conn, _) = get_connection(&state).unwrap();
+    // Function looks weird to get around mutex issues
+    // Returns whether size was updated
+    fn update_size_if_needed(&self) -> Result<bool> {
+        // Geometry and window state are refreshed separately, property changes don't need a GetGeometry
+        let (should_update, should_update_state) = {
+            let mut state = self.state.lock().unwrap();
 
-                    // let cookie = conn.send_request(&GetCursorImage {});
+            let size = std::mem::take(&mut state.needs_size_update) || state.size.is_none();
+            let window_state = std::mem::take(&mut state.needs_state_update);
+            (size, window_state)
+        };
 
-                    // let reply = conn.wait_for_reply(cookie).unwrap();
+        if should_update {
+            let new = self.get_size()?;
+            let old_size = self.state.lock().unwrap().size;
 
-                    // println!("Got cursor: {:?}", reply.cursor_image());
+            if old_size.is_none() || old_size.unwrap() != new {
+                if old_size.is_none() || new.width != old_size.unwrap().width {
+                    self.obj().set_property("width", new.width as u32);
                 }
-                Err(e) => {
-                    error!(CAT, "Failed to get cursor position: {}", e.to_string());
+                if old_size.is_none() || new.height != old_size.unwrap().height {
+                    self.obj().set_property("height", new.height as u32);
+                }
+
+                self.obj().emit_by_name::<()>("resize", &[&(new.width as u32), &(new.height as u32)]);
+
+                let mut state = self.state.lock().unwrap();
+                state.damage_full = true;
+                state.offscreen_stale = state.offscreen;
+            }
+
+            // The root's children are every toplevel, their extents say nothing about the root
+            let capture_root = self.state.lock().unwrap().capture_root;
+
+            let frame_extents = if self.state.lock().unwrap().crop_decorations && !capture_root {
+                self.get_frame_extents()?
+            } else {
+                None
+            };
+
+            let csd_extents = if self.state.lock().unwrap().crop_csd_shadows && !capture_root {
+                self.get_csd_extents()?
+            } else {
+                None
+            };
+
+            let monitor = if capture_root {
+                self.get_monitor()?
+            } else {
+                None
+            };
+
+            let changed_region = {
+                let mut state = self.state.lock().unwrap();
+                let _ = state.size.insert(new);
+                state.frame_extents = frame_extents;
+                state.csd_extents = csd_extents;
+                state.monitor = monitor;
+
+                let (region, clamped) = capture_region(&state).unzip();
+                let clamped = clamped.unwrap_or_default();
+
+                if state.region != region {
+                    debug!(CAT, "Capture region is now {:?}", region);
+                    state.region = region;
+                    state.damage_full = true;
+                    state.max_strip_rows.take();
+
+                    // A failed capture would otherwise fall back to a frame that no longer matches the caps
+                    if state.flush_on_resize {
+                        state.last_frame.take();
+                    }
+
+                    // Only interesting when it isn't simply what was asked for, including when clamping stops
+                    let was_clamped = std::mem::replace(&mut state.region_clamped, clamped);
+                    region.filter(|_| clamped || was_clamped)
+                } else {
+                    None
+                }
+            };
+
+            if let Some(region) = changed_region {
+                self.obj().emit_by_name::<()>("region-changed", &[&(region.x as i32), &(region.y as i32), &(region.width as u32), &(region.height as u32)]);
+            }
+
+            let scale_factor = self.get_scale_factor()?;
+            if scale_factor != self.state.lock().unwrap().scale_factor {
+                self.state.lock().unwrap().scale_factor = scale_factor;
+                self.obj().notify("scale-factor");
+            }
+        }
+
+        if should_update_state {
+            let (new, fullscreen) = self.get_window_state()?;
+            if new != self.state.lock().unwrap().visibility {
+                self.state.lock().unwrap().visibility = new;
+                self.obj().set_property("visibility", new);
+
+                let mut state = self.state.lock().unwrap();
+                if new == WindowVisibility::Hidden && state.stop_on_hidden && !state.eos_requested {
+                    debug!(CAT, "Window was hidden, ending the stream");
+                    state.eos_requested = true;
+                }
+            }
+
+            if fullscreen != self.state.lock().unwrap().fullscreen {
+                self.state.lock().unwrap().fullscreen = fullscreen;
+                self.obj().notify("fullscreen");
+            }
+
+            let (title, title_raw) = self.get_title()?;
+            if title_raw != self.state.lock().unwrap().title_raw {
+                self.state.lock().unwrap().title_raw = title_raw;
+                self.obj().notify("title-raw");
+            }
+
+            if title != self.state.lock().unwrap().title {
+                self.state.lock().unwrap().title = title;
+                self.obj().notify("title");
+            }
+        }
+
+        Ok(should_update)
+    }
+
+    // Holds the capture until the window finishes presenting a frame, so it isn't grabbed mid-paint
+    // Gives up after one frame interval, windows that don't use Present are grabbed as usual
+    fn wait_for_present(&self) {
+        let (present_frame, timeout) = {
+            let state = self.state.lock().unwrap();
+            if state.capture_backend != CaptureBackend::Present || !state.present_active {
+                return;
+            }
+
+            (state.present_frame.clone(), Duration::from_nanos(frame_interval(&state).nseconds()))
+        };
+
+        let (presented, cvar) = &*present_frame;
+        let (mut presented, _) = cvar.wait_timeout_while(presented.lock().unwrap(), timeout, |presented| !*presented).unwrap();
+
+        if !*presented {
+            trace!(CAT, "No frame presented within {:?}, capturing anyway", timeout);
+        }
+        *presented = false;
+    }
+
+    // Stamps the buffer with the current time in the configured timestamp mode
+    fn timestamp(&self, mut buf: gst::Buffer) -> gst::Buffer {
+        let (mode, x_server_time) = {
+            let state = self.state.lock().unwrap();
+            (state.timestamp_mode, state.x_server_time)
+        };
+
+        let pts = match (mode, x_server_time) {
+            (TimestampMode::RunningTime, _) => self.running_time(),
+            (TimestampMode::Monotonic, _) => Some(gst::util_get_timestamp()),
+            (TimestampMode::XServerTime, Some((time, seen))) => Some(gst::ClockTime::from_mseconds(time as u64) + gst::ClockTime::from_nseconds(seen.elapsed().as_nanos() as u64)),
+            (TimestampMode::XServerTime, None) => {
+                trace!(CAT, "No X event timestamp seen yet, using running time");
+                self.running_time()
+            }
+        };
+
+        // Reused frames and clock jumps must never repeat or go back in time, muxers reject that
+        let (pts, duration) = {
+            let mut state = self.state.lock().unwrap();
+            let pts = match (pts, state.last_pts) {
+                (Some(pts), Some(last_pts)) if pts <= last_pts => Some(last_pts + frame_interval(&state)),
+                (pts, _) => pts
+            };
+            if let (Some(pts), Some(last_pts)) = (pts, state.last_pts) {
+                let _ = state.last_pts_delta.insert(pts - last_pts);
+            }
+            state.last_pts = pts.or(state.last_pts);
+            (pts, expected_duration(&state))
+        };
+
+        // Any buffer can turn out to be the last before EOS, so none is left open-ended for muxers
+        let buf_mut = buf.make_mut();
+        buf_mut.set_pts(pts);
+        buf_mut.set_duration(duration);
+        buf
+    }
+
+    fn running_time(&self) -> Option<gst::ClockTime> {
+        let obj = self.obj();
+        obj.clock().zip(obj.base_time()).map(|(clock, base_time)| clock.time().unwrap_or_default().saturating_sub(base_time))
+    }
+
+    // Blocks until the clock reaches time, unless unlock cuts the wait short with Flushing
+    fn wait_on_clock(&self, clock: &gst::Clock, time: gst::ClockTime) -> Result<(), gst::FlowError> {
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            if state.unlocked {
+                return Err(gst::FlowError::Flushing);
+            }
+
+            state.clock_wait.insert(clock.new_single_shot_id(time)).clone()
+        };
+
+        let (res, _) = id.wait();
+        self.state.lock().unwrap().clock_wait.take();
+
+        match res {
+            Err(gst::ClockError::Unscheduled) => Err(gst::FlowError::Flushing),
+            _ => Ok(())
+        }
+    }
+
+    // The first size update usually happens during negotiation, before applications connect their handlers
+    // Repeats it once streaming so handlers connected right after set_state still see the initial state
+    fn announce_initial_state(&self) {
+        let size = {
+            let mut state = self.state.lock().unwrap();
+            if state.initial_state_announced {
+                return;
+            }
+
+            match state.size {
+                Some(size) => {
+                    state.initial_state_announced = true;
+                    size
+                }
+                None => return
+            }
+        };
+
+        let obj = self.obj();
+        obj.emit_by_name::<()>("resize", &[&(size.width as u32), &(size.height as u32)]);
+        obj.notify("width");
+        obj.notify("height");
+        obj.notify("visibility");
+    }
+
+    // Called once for every pushed frame
+    fn set_last_frame_fresh(&self, fresh: bool) {
+        let (changed, stats_updated) = {
+            let mut state = self.state.lock().unwrap();
+            let stats_updated = state.benchmark && self.record_benchmark_frame(&mut state, fresh);
+            (std::mem::replace(&mut state.last_frame_was_fresh, fresh) != fresh, stats_updated)
+        };
+
+        if changed {
+            self.obj().notify("last-frame-was-fresh");
+        }
+
+        if stats_updated {
+            self.obj().notify("stats");
+        }
+    }
+
+    // Returns whether a one second window completed and stats was replaced
+    fn record_benchmark_frame(&self, state: &mut MutexGuard<State>, fresh: bool) -> bool {
+        let window = &mut state.benchmark_window;
+        let start = *window.start.get_or_insert_with(Instant::now);
+
+        window.frames += 1;
+        if !fresh {
+            window.repeated += 1;
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+
+        let window = std::mem::take(&mut state.benchmark_window);
+
+        let achieved = window.frames as f64 / elapsed.as_secs_f64();
+        let requested = if state.frame_duration.is_zero() {
+            0.0
+        } else {
+            1.0 / state.frame_duration.as_secs_f64()
+        };
+        let round_trip = window.round_trip_total.checked_div(window.round_trips).unwrap_or_default();
+        let repeated = window.repeated as f64 / window.frames as f64;
+
+        info!(CAT, imp: self, "Benchmark: {:.2}/{:.2} fps, average round-trip {:?}, {:.1}% repeated frames", achieved, requested, round_trip, repeated * 100.0);
+
+        let stats = gst::Structure::builder("ximageredux-stats")
+            .field("achieved-framerate", achieved)
+            .field("requested-framerate", requested)
+            .field("average-round-trip", gst::ClockTime::try_from(round_trip).unwrap_or(gst::ClockTime::ZERO))
+            .field("repeated-fraction", repeated)
+            .field("watcher-restarts", state.watcher_restarts)
+            .build();
+        let _ = state.stats.insert(stats);
+
+        true
+    }
+
+    // Asks downstream, then the application or bin, for a connection another element already opened
+    fn shared_connection(&self) -> Option<SharedConnection> {
+        if let Some(shared) = self.state.lock().unwrap().context_connection.clone() {
+            return Some(shared);
+        }
+
+        let obj = self.obj();
+
+        let mut query = gst::query::Context::new(CONNECTION_CONTEXT_TYPE);
+        let context = match obj.src_pad().peer_query(&mut query) {
+            true => query.context_owned(),
+            false => None
+        }.or_else(|| {
+            // Bins answer this synchronously with any context they've seen posted
+            let _ = obj.post_message(gst::message::NeedContext::builder(CONNECTION_CONTEXT_TYPE).src(&*obj).build());
+            obj.context(CONNECTION_CONTEXT_TYPE)
+        })?;
+
+        match context.structure().get::<SharedConnection>("connection") {
+            Ok(shared) => Some(shared),
+            Err(e) => {
+                warning!(CAT, "Ignoring invalid connection context: {}", e);
+                None
+            }
+        }
+    }
+
+    // Lets other elements in the pipeline reuse this element's connection
+    fn post_connection_context(&self, shared: &SharedConnection) {
+        let obj = self.obj();
+        let context = connection_context(shared);
+
+        obj.set_context(&context);
+        let _ = obj.post_message(gst::message::HaveContext::builder(context).src(&*obj).build());
+    }
+
+    fn get_size(&self) -> Result<Size> {
+        let mut state = self.state.lock().unwrap();
+        let (conn, xid) = get_connection(&state)?;
+        
+        let cookie = conn.send_request(&GetGeometry {
+            drawable: Drawable::Window(xcb::XidNew::new(xid))
+        });
+
+        let reply = wait_for_reply(conn, cookie)?;
+
+        // GetGeometry is relative to the parent, which is the WM frame for managed windows
+        let cookie = conn.send_request(&TranslateCoordinates {
+            src_window: xcb::XidNew::new(xid),
+            dst_window: reply.root(),
+            src_x: 0,
+            src_y: 0
+        });
+
+        let translated = wait_for_reply(conn, cookie)?;
+
+        let _ = state.position.insert(Position {
+            x: translated.dst_x(),
+            y: translated.dst_y()
+        });
+
+        // GetImage data follows the new depth right away, so the caps have to follow too
+        if forget_changed_depth(&mut state, reply.depth()) {
+            warning!(CAT, "Window depth changed to {}, renegotiating", reply.depth());
+        }
+
+        Ok(Size {
+            width: reply.width(),
+            height: reply.height()
+        })
+    }
+
+    // Returns the visibility and whether the window is fullscreen
+    fn get_window_state(&self) -> Result<(WindowVisibility, bool)> {
+        let mut state = self.state.lock().unwrap();
+        let atoms = get_atoms(&mut state)?;
+        let (conn, xid) = get_connection(&state)?;
+
+        window::get_state(conn, &atoms, xcb::XidNew::new(xid))
+    }
+
+    // Selected output's geometry, None to capture the whole root
+    fn get_monitor(&self) -> Result<Option<Region>> {
+        let state = self.state.lock().unwrap();
+        if state.monitor_name.is_none() && state.monitor_index < 0 {
+            return Ok(None);
+        }
+
+        if !state.randr_available {
+            warning!(CAT, "RandR 1.3 is unavailable, capturing every monitor");
+            return Ok(None);
+        }
+
+        let (conn, xid) = get_connection(&state)?;
+        let monitors = monitor::list(conn, xcb::XidNew::new(xid))?;
+
+        let found = match state.monitor_name.as_deref() {
+            Some(name) => monitors.iter().find(|monitor| monitor.name == name),
+            None => monitors.get(state.monitor_index as usize)
+        };
+
+        Ok(match found {
+            Some(monitor) => Some(Region { x: monitor.x, y: monitor.y, width: monitor.width, height: monitor.height }),
+            None => {
+                warning!(CAT, "Monitor {:?} isn't active, capturing every monitor. Active monitors: {:?}",
+                    state.monitor_name.clone().unwrap_or_else(|| state.monitor_index.to_string()),
+                    monitors.iter().map(|monitor| monitor.name.as_str()).collect::<Vec<_>>());
+                None
+            }
+        })
+    }
+
+    fn get_csd_extents(&self) -> Result<Option<FrameExtents>> {
+        let mut state = self.state.lock().unwrap();
+        let atoms = get_atoms(&mut state)?;
+        let (conn, xid) = get_connection(&state)?;
+
+        window::get_csd_extents(conn, &atoms, xcb::XidNew::new(xid))
+    }
+
+    fn get_frame_extents(&self) -> Result<Option<FrameExtents>> {
+        let mut state = self.state.lock().unwrap();
+        let atoms = get_atoms(&mut state)?;
+        let (conn, xid) = get_connection(&state)?;
+
+        window::get_frame_extents(conn, &atoms, xcb::XidNew::new(xid))
+    }
+
+    // Returns the decoded title and the bytes it was decoded from
+    fn get_title(&self) -> Result<(Option<String>, Option<Vec<u8>>)> {
+        let mut state = self.state.lock().unwrap();
+        let atoms = get_atoms(&mut state)?;
+        let (conn, xid) = get_connection(&state)?;
+
+        Ok(match window::get_title_raw(conn, &atoms, xcb::XidNew::new(xid))? {
+            Some((r#type, bytes)) => (Some(window::decode_text(&atoms, r#type, &bytes)), Some(bytes)),
+            None => (None, None)
+        })
+    }
+
+    // Compares the CRTC under the window's center with its mode, so outputs scaled with a transform report e.g. 2.0
+    // Falls back to 1.0 without RandR or if the window isn't on any active CRTC
+    fn get_scale_factor(&self) -> Result<f64> {
+        let state = self.state.lock().unwrap();
+        let (conn, _) = get_connection(&state)?;
+
+        let (position, size) = match (state.position, state.size) {
+            (Some(position), Some(size)) if state.randr_available => (position, size),
+            _ => return Ok(1.0)
+        };
+
+        let root = match conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize) {
+            Some(screen) => screen.root(),
+            None => bail!("Screen {:?} doesn't exist on the server", state.screen_num)
+        };
+
+        let resources = wait_for_reply(conn, conn.send_request(&xcb::randr::GetScreenResourcesCurrent {
+            window: root
+        }))?;
+
+        let cookies: Vec<_> = resources.crtcs().iter().map(|crtc| conn.send_request(&xcb::randr::GetCrtcInfo {
+            crtc: *crtc,
+            config_timestamp: resources.config_timestamp()
+        })).collect();
+
+        let center_x = position.x as i32 + size.width as i32 / 2;
+        let center_y = position.y as i32 + size.height as i32 / 2;
+
+        for cookie in cookies {
+            let crtc = wait_for_reply(conn, cookie)?;
+
+            let contains_center = (crtc.x() as i32..crtc.x() as i32 + crtc.width() as i32).contains(&center_x) &&
+                (crtc.y() as i32..crtc.y() as i32 + crtc.height() as i32).contains(&center_y);
+
+            if crtc.mode().resource_id() == 0 || !contains_center {
+                continue;
+            }
+
+            let mode = match resources.modes().iter().find(|mode| mode.id == crtc.mode().resource_id()) {
+                Some(mode) => mode,
+                None => continue
+            };
+
+            // Modes are in output orientation, the CRTC area is already rotated
+            let mode_width = if crtc.rotation().intersects(xcb::randr::Rotation::ROTATE_90 | xcb::randr::Rotation::ROTATE_270) {
+                mode.height
+            } else {
+                mode.width
+            };
+
+            if mode_width == 0 {
+                continue;
+            }
+
+            return Ok(crtc.width() as f64 / mode_width as f64);
+        }
+
+        Ok(1.0)
+    }
+
+    // Errors from unchecked requests are queued as events, nothing else reads events from the capture connection
+    fn drain_x_errors(&self) {
+        let state = self.state.lock().unwrap();
+        let conn = match state.connection.as_ref() {
+            Some(conn) => conn,
+            None => return
+        };
+
+        loop {
+            match conn.poll_for_event() {
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(xcb::Error::Protocol(e)) => log_x_error("capture", &e),
+                Err(e) => {
+                    warning!(CAT, "X connection error: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    // Opens the connection at NULL->READY, posting an error if the display can't be reached
+    fn check_connection(&self) -> Result<(), gst::StateChangeError> {
+        #[cfg(feature = "test-inject")]
+        if self.state.lock().unwrap().injected_size.is_some() {
+            return Ok(());
+        }
+
+        if self.state.lock().unwrap().connection.is_some() {
+            return Ok(());
+        }
+
+        self.open_connection().map_err(|e| {
+            gst::element_imp_error!(self, gst::ResourceError::OpenRead, ["Failed to open X11 display: {}", e]);
+            gst::StateChangeError
+        })
+    }
+
+    fn open_connection(&self) -> Result<()> {
+        let (display, xauthority) = {
+            let state = self.state.lock().unwrap();
+            (state.display.clone(), state.xauthority.clone())
+        };
+
+        let SharedConnection { connection, screen_num, .. } = match self.shared_connection().filter(|shared| shared.display == display) {
+            Some(shared) => {
+                debug!(CAT, "Using connection shared by the pipeline");
+                shared
+            }
+            None => {
+                let shared = match xauth::connect(display.as_deref(), xauthority.as_deref(), &[xcb::Extension::XFixes, xcb::Extension::Composite, xcb::Extension::Test, xcb::Extension::RandR, xcb::Extension::Input, xcb::Extension::Shm]) {
+                    Ok((c, s)) => SharedConnection { connection: Arc::new(c), screen_num: s, display },
+                    Err(e) => bail!("Failed to connect to X11 server: {}", e)
+                };
+
+                self.post_connection_context(&shared);
+                shared
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+
+        // XFixes must be told which version we speak before any of its requests are used
+        state.xfixes_available = connection.active_extensions().any(|ext| ext == xcb::Extension::XFixes) && {
+            let cookie = connection.send_request(&xcb::xfixes::QueryVersion {
+                client_major_version: 4,
+                client_minor_version: 0
+            });
+
+            connection.wait_for_reply(cookie).is_ok()
+        };
+
+        if !state.xfixes_available {
+            warning!(CAT, "XFixes is unavailable, the real cursor can't be captured");
+        }
+
+        state.composite_available = connection.active_extensions().any(|ext| ext == xcb::Extension::Composite) && {
+            let cookie = connection.send_request(&xcb::composite::QueryVersion {
+                client_major_version: 0,
+                client_minor_version: 4
+            });
+
+            connection.wait_for_reply(cookie).is_ok()
+        };
+
+        state.xtest_available = connection.active_extensions().any(|ext| ext == xcb::Extension::Test) && {
+            let cookie = connection.send_request(&xcb::xtest::GetVersion {
+                major_version: 2,
+                minor_version: 2
+            });
+
+            connection.wait_for_reply(cookie).is_ok()
+        };
+
+        state.shm_available = connection.active_extensions().any(|ext| ext == xcb::Extension::Shm) && {
+            let cookie = connection.send_request(&xcb::shm::QueryVersion {});
+            connection.wait_for_reply(cookie).is_ok()
+        };
+
+        // XIQueryPointer needs XInput 2.0
+        state.xinput_available = connection.active_extensions().any(|ext| ext == xcb::Extension::Input) && {
+            let cookie = connection.send_request(&xcb::xinput::XiQueryVersion {
+                major_version: 2,
+                minor_version: 0
+            });
+
+            connection.wait_for_reply(cookie).is_ok_and(|reply| reply.major_version() >= 2)
+        };
+
+        if state.pointer_device >= 0 && !state.xinput_available {
+            warning!(CAT, "XInput2 is unavailable, tracking the core pointer instead of device {}", state.pointer_device);
+        }
+
+        if state.highlight_clicks && !state.xinput_available {
+            warning!(CAT, "XInput2 is unavailable, clicks won't be highlighted");
+        }
+
+        // GetScreenResourcesCurrent needs 1.3
+        state.randr_available = connection.active_extensions().any(|ext| ext == xcb::Extension::RandR) && {
+            let cookie = connection.send_request(&xcb::randr::QueryVersion {
+                major_version: 1,
+                minor_version: 3
+            });
+
+            connection.wait_for_reply(cookie).is_ok_and(|reply| (reply.major_version(), reply.minor_version()) >= (1, 3))
+        };
+
+        // Enables BIG-REQUESTS if the server has it, without it windows past ~256KB are captured in strips
+        let max_request_length = connection.get_maximum_request_length();
+        if max_request_length > connection.get_setup().maximum_request_length() as u32 {
+            debug!(CAT, "BIG-REQUESTS enabled, maximum request length is {} bytes", max_request_length as u64 * 4);
+        } else {
+            debug!(CAT, "BIG-REQUESTS unavailable, windows over {} bytes are captured in strips", max_request_length as u64 * 4);
+        }
+
+        // Servers with several screens (:0.1 and so on) share one connection
+        // A screen that doesn't exist is left on the default one here and rejected by start
+        let screen_num = if screen_exists(&connection, state.screen) {
+            state.screen
+        } else {
+            screen_num
+        };
+
+        if let Some(screen) = connection.get_setup().roots().nth(screen_num as usize) {
+            state.cursor_auto_scale = monitor::dpi(&connection, screen).map_or(1.0, |dpi| dpi / BASE_DPI);
+            debug!(CAT, "Automatic cursor scale is {}", state.cursor_auto_scale);
+        }
+
+        let _ = state.connection.insert(connection);
+        let _ = state.screen_num.insert(screen_num);
+
+        Ok(())
+    }
+
+    unsafe fn get_video_format(&self) -> Result<i32> {
+        let mut state = self.state.lock().unwrap();
+        // Blank frames while waiting for the window use the root's format
+        let (conn, xid) = if state.root_watch.is_some() {
+            let conn: &Connection = match state.connection.as_ref() {
+                Some(conn) => conn,
+                None => bail!("Not connected!")
+            };
+
+            match conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize) {
+                Some(screen) => (conn, screen.root().resource_id()),
+                None => bail!("Screen {:?} doesn't exist on the server", state.screen_num)
+            }
+        } else {
+            get_connection(&state)?
+        };
+
+        let setup = conn.get_setup();
+        let mut endianness = match setup.bitmap_format_bit_order() {
+            ImageOrder::MsbFirst => G_BIG_ENDIAN,
+            ImageOrder::LsbFirst => G_LITTLE_ENDIAN
+        };
+
+        let cookie = conn.send_request(&GetGeometry {
+            drawable: Drawable::Window(xcb::XidNew::new(xid))
+        });
+        let attributes_cookie = conn.send_request(&GetWindowAttributes {
+            window: xcb::XidNew::new(xid)
+        });
+
+        let geometry_reply = wait_for_reply(conn, cookie)?;
+        let attributes_reply = wait_for_reply(conn, attributes_cookie)?;
+
+        let pixmap_format = match setup.pixmap_formats().iter().find(|fmt| fmt.depth() == geometry_reply.depth()) {
+            Some(fmt) => fmt,
+            None => bail!("No pixmap format for depth {}", geometry_reply.depth())
+        };
+        let bpp = pixmap_format.bits_per_pixel();
+
+        let pixmap_format = PixmapFormat {
+            bits_per_pixel: bpp,
+            scanline_pad: pixmap_format.scanline_pad()
+        };
+
+        // The stored screen can be stale after the server was reconfigured, so fall back to the screen owning the window
+        let screen_num = match state.screen_num.filter(|num| *num >= 0 && (*num as usize) < setup.roots().count()) {
+            Some(num) => num,
+            None => match setup.roots().position(|screen| screen.root() == geometry_reply.root()) {
+                Some(num) => {
+                    warning!(CAT, "Screen {:?} doesn't exist on the server, using screen {} instead", state.screen_num, num);
+                    num as i32
+                }
+                None => bail!("No screen on the server has the window's root")
+            }
+        };
+
+        let screen = match setup.roots().nth(screen_num as usize) {
+            Some(screen) => screen,
+            None => bail!("Screen {} doesn't exist on the server", screen_num)
+        };
+
+        // ARGB windows on a 24-bit root have their own visual, whose masks describe the GetImage data
+        let find_visual = |id: x::Visualid| screen.allowed_depths()
+            .flat_map(|depth| depth.visuals().iter())
+            .find(|vis| vis.visual_id() == id)
+            .copied();
+
+        let visual = match find_visual(attributes_reply.visual()) {
+            Some(visual) => visual,
+            None => {
+                warning!(CAT, "Window visual {:#x} not found on screen {}, using the root visual", attributes_reply.visual(), screen_num);
+
+                match find_visual(screen.root_visual()) {
+                    Some(visual) => visual,
+                    None => bail!("Root visual of screen {} not found", screen_num)
+                }
+            }
+        };
+
+        // Our caps system handles 24/32bpp RGB as big-endian
+        let (red_mask, green_mask, blue_mask) = if (bpp == 24 || bpp == 32) && endianness == G_LITTLE_ENDIAN {
+            endianness = G_BIG_ENDIAN;
+            let mut set = (visual.red_mask().to_be(), visual.green_mask().to_be(), visual.blue_mask().to_be());
+
+            if bpp == 24 {
+                set.0 >>= 8;
+                set.1 >>= 8;
+                set.2 >>= 8;
+            }
+
+            set
+        } else {
+            (visual.red_mask(), visual.green_mask(), visual.blue_mask())
+        };
+
+        let alpha_mask = if bpp == 32 {
+            !(red_mask | green_mask | blue_mask)
+        } else {
+            0
+        };
+
+        let _ = state.pixmap_format.insert(pixmap_format);
+        let _ = state.depth.insert(geometry_reply.depth());
+        let _ = state.screen_num.insert(screen_num);
+
+        Ok(gst_video_format_from_masks(geometry_reply.depth().into(), bpp.into(), endianness, red_mask, green_mask, blue_mask, alpha_mask))
+    }
+
+    // Has the server composite child windows into the target so GetImage sees their current content
+    fn redirect_subwindows(&self) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let (conn, xid) = get_connection(&state)?;
+
+        if !state.composite_available {
+            bail!("Composite extension is unavailable");
+        }
+
+        conn.send_and_check_request(&xcb::composite::RedirectSubwindows {
+            window: xcb::XidNew::new(xid),
+            update: xcb::composite::Redirect::Automatic
+        })?;
+
+        drop(state);
+        self.state.lock().unwrap().subwindows_redirected = true;
+
+        Ok(())
+    }
+
+    // Redirects the window and names its current pixmap, which keeps the last content after the window is unmapped
+    // An unmapped window has no pixmap to name, so a minimized window keeps the previously named one
+    fn name_offscreen_pixmap(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.composite_available {
+            bail!("Composite extension is unavailable");
+        }
+
+        let (conn, xid) = match (state.connection.clone(), state.xid) {
+            (Some(conn), Some(xid)) => (conn, xid),
+            _ => bail!("Not connected!")
+        };
+        let window = xcb::XidNew::new(xid);
+
+        // Automatic redirection can be shared with a compositor, unlike manual
+        if !state.window_redirected {
+            conn.send_and_check_request(&xcb::composite::RedirectWindow {
+                window,
+                update: xcb::composite::Redirect::Automatic
+            })?;
+            state.window_redirected = true;
+        }
+
+        let pixmap = conn.generate_id();
+        conn.send_and_check_request(&xcb::composite::NameWindowPixmap { window, pixmap })?;
+
+        let border = match wait_for_reply(&conn, conn.send_request(&GetGeometry { drawable: Drawable::Window(window) })) {
+            Ok(geometry) => geometry.border_width() as i16,
+            Err(e) => {
+                conn.send_request(&x::FreePixmap { pixmap });
+                return Err(e);
+            }
+        };
+
+        free_offscreen_pixmap(&mut state);
+        let _ = state.offscreen_pixmap.insert((pixmap, border));
+        debug!(CAT, "Capturing window {:#x} offscreen from pixmap {:#x}", xid, pixmap.resource_id());
+
+        Ok(())
+    }
+
+    // Lighter than a Composite redirect, but servers are free to ignore it, in which case GetImage
+    // keeps returning whatever covers the obscured parts of the window
+    fn set_backing_store(&self) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let (conn, xid) = get_connection(&state)?;
+        let window = xcb::XidNew::new(xid);
+
+        let requested = match state.backing_store {
+            BackingStore::Unchanged => return Ok(()),
+            BackingStore::WhenMapped => x::BackingStore::WhenMapped,
+            BackingStore::Always => x::BackingStore::Always
+        };
+
+        // The screen advertises the most it will honor
+        let supported = conn.get_setup().roots()
+            .nth(state.screen_num.unwrap_or(0) as usize)
+            .map(|screen| screen.backing_stores())
+            .unwrap_or(x::BackingStore::NotUseful);
+        if supported == x::BackingStore::NotUseful {
+            bail!("The server doesn't support backing store");
+        }
+
+        let attributes = wait_for_reply(conn, conn.send_request(&GetWindowAttributes { window }))?;
+
+        conn.send_and_check_request(&ChangeWindowAttributes {
+            window,
+            value_list: &[Cw::BackingStore(requested)]
+        })?;
+
+        drop(state);
+        let _ = self.state.lock().unwrap().original_backing_store.insert(attributes.backing_store());
+
+        Ok(())
+    }
+
+    // Undoes everything done to the server on behalf of the capture connection
+    // The server would also clean up on disconnect, but a shared or reused connection wouldn't be closed
+    fn release_server_resources(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        // Every step runs even if an earlier one fails, so one bad request doesn't leak the rest
+        let mut first_error = None;
+        let mut record = |step: &str, result: Result<()>| {
+            if let Err(e) = result {
+                warning!(CAT, "Failed to {}: {}", step, e);
+                first_error.get_or_insert(e);
+            }
+        };
+
+        if std::mem::take(&mut state.subwindows_redirected) {
+            record("unredirect subwindows", get_connection(&state).and_then(|(conn, xid)| {
+                conn.send_and_check_request(&xcb::composite::UnredirectSubwindows {
+                    window: xcb::XidNew::new(xid),
+                    update: xcb::composite::Redirect::Automatic
+                }).map_err(Into::into)
+            }));
+        }
+
+        free_offscreen_pixmap(&mut state);
+
+        if std::mem::take(&mut state.window_redirected) {
+            record("unredirect window", get_connection(&state).and_then(|(conn, xid)| {
+                conn.send_and_check_request(&xcb::composite::UnredirectWindow {
+                    window: xcb::XidNew::new(xid),
+                    update: xcb::composite::Redirect::Automatic
+                }).map_err(Into::into)
+            }));
+        }
+
+        if let Some(segment) = state.shm_segment.take() {
+            record("detach shared memory segment", get_connection(&state).and_then(|(conn, _)| segment.release(conn)));
+        }
+
+        if let Some(original) = state.original_backing_store.take() {
+            record("restore backing store", get_connection(&state).and_then(|(conn, xid)| {
+                conn.send_and_check_request(&ChangeWindowAttributes {
+                    window: xcb::XidNew::new(xid),
+                    value_list: &[Cw::BackingStore(original)]
+                }).map_err(Into::into)
+            }));
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(())
+        }
+    }
+
+    // Ends the stream, first pushing a copy of the last frame if configured so encoders can flush cleanly
+    fn finish_stream(&self) -> Result<CreateSuccess, gst::FlowError> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.repeat_last_frame_on_eos && !state.final_frame_sent {
+            if let Some(last) = state.last_frame.as_ref() {
+                let mut buf = last.copy();
+                {
+                    // Muxers need the last frame's duration to close the stream, so it's never left open-ended
+                    let duration = expected_duration(&state);
+                    let buf = buf.get_mut().unwrap();
+                    if let Some(pts) = last.pts() {
+                        buf.set_pts(pts + last.duration().filter(|d| !d.is_zero()).unwrap_or(duration));
+                    }
+                    buf.set_duration(duration);
+                }
+
+                state.final_frame_sent = true;
+                return Ok(CreateSuccess::NewBuffer(buf));
+            }
+        }
+
+        Err(gst::FlowError::Eos)
+    }
+
+    #[cfg(feature = "test-inject")]
+    fn inject_frame(&self, buffer: gst::Buffer, width: u32, height: u32) -> Result<()> {
+        let size = Size {
+            width: width.try_into()?,
+            height: height.try_into()?
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.injected_frames.push_back(buffer);
+        let _ = state.injected_size.insert(size);
+
+        Ok(())
+    }
+
+    // Next injected frame, renegotiating first if its size differs from the current caps
+    #[cfg(feature = "test-inject")]
+    fn create_injected(&self, size: Size) -> Result<CreateSuccess, gst::FlowError> {
+        let needs_negotiation = {
+            let mut state = self.state.lock().unwrap();
+            let _ = state.size.insert(size);
+            !state.video_info.as_ref().is_some_and(|info| info.width() == size.width as u32 && info.height() == size.height as u32)
+        };
+
+        if needs_negotiation {
+            if let Err(e) = self.negotiate() {
+                error!(CAT, "Failed to negotiate injected frame: {}", e);
+                return Err(gst::FlowError::NotNegotiated);
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let frame = match state.injected_frames.pop_front().or_else(|| state.last_frame.clone()) {
+            Some(frame) => frame,
+            None => return Err(gst::FlowError::Error)
+        };
+
+        let _ = state.last_frame.insert(frame.clone());
+
+        Ok(CreateSuccess::NewBuffer(frame))
+    }
+
+    fn template_caps(&self) -> gst::Caps {
+        self.obj().pad_template_list().iter().next().unwrap().caps().copy()
+    }
+
+    // Moves the pointer to (x, y) relative to the captured window
+    // XTest is preferred since it generates real motion events, WarpPointer is the fallback
+    fn warp_pointer(&self, x: i16, y: i16) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let (conn, xid) = get_connection(&state)?;
+        let win = xcb::XidNew::new(xid);
+
+        if state.xtest_available {
+            let cookie = conn.send_request(&GetGeometry {
+                drawable: Drawable::Window(win)
+            });
+            let root = wait_for_reply(conn, cookie)?.root();
+
+            let cookie = conn.send_request(&TranslateCoordinates {
+                src_window: win,
+                dst_window: root,
+                src_x: x,
+                src_y: y
+            });
+            let translated = wait_for_reply(conn, cookie)?;
+
+            conn.send_and_check_request(&xcb::xtest::FakeInput {
+                r#type: <x::MotionNotifyEvent as xcb::BaseEvent>::NUMBER as u8,
+                detail: 0,
+                time: x::CURRENT_TIME,
+                root,
+                root_x: translated.dst_x(),
+                root_y: translated.dst_y(),
+                deviceid: 0
+            })?;
+        } else {
+            conn.send_and_check_request(&x::WarpPointer {
+                src_window: x::Window::none(),
+                dst_window: win,
+                src_x: 0,
+                src_y: 0,
+                src_width: 0,
+                src_height: 0,
+                dst_x: x,
+                dst_y: y
+            })?;
+        }
+
+        let _ = state.last_cursor_position.insert(Position { x, y });
+
+        Ok(())
+    }
+
+    // Picks the window to capture from the selection properties when no xid is set
+    fn resolve_window(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.xid.is_some() {
+            return Ok(());
+        }
+
+        if state.capture_root {
+            let conn = match state.connection.as_ref() {
+                Some(conn) => conn,
+                None => bail!("Not connected!")
+            };
+
+            let root = match conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize) {
+                Some(screen) => screen.root().resource_id(),
+                None => bail!("Screen {:?} doesn't exist on the server", state.screen_num)
+            };
+
+            debug!(CAT, "Capturing root window {:#x}", root);
+            let _ = state.xid.insert(root);
+            state.xid_resolved = true;
+            return Ok(());
+        }
+
+        if state.select_on_start {
+            drop(state);
+            let xid = self.pick_window()?;
+
+            let mut state = self.state.lock().unwrap();
+            let _ = state.xid.insert(xid);
+            state.xid_resolved = true;
+            return Ok(());
+        }
+
+        if state.follow_active_window {
+            drop(state);
+            return match self.active_window()? {
+                Some(xid) => {
+                    let mut state = self.state.lock().unwrap();
+                    let _ = state.xid.insert(xid);
+                    state.xid_resolved = true;
+                    Ok(())
+                }
+                None => bail!("No window is active")
+            };
+        }
+
+        if !has_selection(&state) {
+            bail!("XID is not set and no window selection criteria are set!");
+        }
+
+        let atoms = get_atoms(&mut state)?;
+        let conn = match state.connection.as_ref() {
+            Some(conn) => conn,
+            None => bail!("Not connected!")
+        };
+
+        let root = match conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize) {
+            Some(screen) => screen.root(),
+            None => bail!("Screen {:?} doesn't exist on the server", state.screen_num)
+        };
+
+        let window_type = window::window_type_atom(conn, state.window_type)?;
+        let name_matcher = state.window_name.as_deref().map(|name| NameMatcher::new(state.name_match, name)).transpose()?;
+
+        let mut found = None;
+        for candidate in window::client_list(conn, &atoms, root)? {
+            // Windows destroyed mid-search just don't match
+            let type_matches = match window_type {
+                Some(window_type) => window::get_window_types(conn, &atoms, candidate).is_ok_and(|types| types.contains(&window_type)),
+                None => true
+            };
+
+            let matches_name = || match name_matcher.as_ref() {
+                Some(matcher) => {
+                    let title = window::get_title(conn, &atoms, candidate).ok().flatten();
+                    let class = window::get_class(conn, candidate).ok().flatten();
+
+                    let matches = title.iter()
+                        .chain(class.iter().flat_map(|(instance, class)| [instance, class]))
+                        .any(|candidate| matcher.matches(candidate));
+                    matches
+                }
+                None => true
+            };
+
+            let matches_class = || match state.window_class.as_deref() {
+                Some(wanted) => window::get_class(conn, candidate).ok().flatten().is_some_and(|(instance, class)| class_matches(wanted, &instance, &class)),
+                None => true
+            };
+
+            let matches_pid = || state.window_pid == 0 || window::get_pid(conn, &atoms, candidate).ok().flatten() == Some(state.window_pid);
+
+            if type_matches && matches_pid() && matches_name() && matches_class() {
+                found = Some(candidate.resource_id());
+                break;
+            }
+        }
+
+        match found {
+            Some(xid) => {
+                debug!(CAT, "Resolved window {:#x}", xid);
+                let _ = state.xid.insert(xid);
+                state.xid_resolved = true;
+                Ok(())
+            }
+            None => bail!("No window matches {}", describe_selection(&state))
+        }
+    }
+
+    // Grabs the pointer with a crosshair and waits for a click, any button but the first cancels
+    // Blocks until the user clicks
+    fn pick_window(&self) -> Result<Xid> {
+        let mut state = self.state.lock().unwrap();
+        let atoms = get_atoms(&mut state)?;
+        let conn = match state.connection.clone() {
+            Some(conn) => conn,
+            None => bail!("Not connected!")
+        };
+        let screen_num = state.screen_num.unwrap_or(0);
+        drop(state);
+
+        let root = match conn.get_setup().roots().nth(screen_num as usize) {
+            Some(screen) => screen.root(),
+            None => bail!("Screen {} doesn't exist on the server", screen_num)
+        };
+
+        // Same glyphs xwininfo uses, XC_crosshair and its mask from the standard cursor font
+        let font: x::Font = conn.generate_id();
+        conn.send_and_check_request(&x::OpenFont { fid: font, name: b"cursor" })?;
+        let cursor: x::Cursor = conn.generate_id();
+        let res = conn.send_and_check_request(&x::CreateGlyphCursor {
+            cid: cursor,
+            source_font: font,
+            mask_font: font,
+            source_char: 34,
+            mask_char: 35,
+            fore_red: 0,
+            fore_green: 0,
+            fore_blue: 0,
+            back_red: u16::MAX,
+            back_green: u16::MAX,
+            back_blue: u16::MAX
+        });
+        conn.send_request(&x::CloseFont { font });
+        res?;
+
+        let grab = wait_for_reply(&conn, conn.send_request(&x::GrabPointer {
+            owner_events: false,
+            grab_window: root,
+            event_mask: EventMask::BUTTON_PRESS,
+            pointer_mode: x::GrabMode::Async,
+            keyboard_mode: x::GrabMode::Async,
+            confine_to: x::Window::none(),
+            cursor,
+            time: x::CURRENT_TIME
+        }));
+        conn.send_request(&x::FreeCursor { cursor });
+
+        if let Ok(reply) = &grab {
+            if reply.status() != x::GrabStatus::Success {
+                bail!("Failed to grab the pointer to select a window: {:?}", reply.status());
+            }
+        }
+        grab?;
+
+        gst::info!(CAT, imp: self, "Click the window to capture");
+
+        let clicked = self.wait_for_click(&conn);
+        conn.send_and_check_request(&x::UngrabPointer { time: x::CURRENT_TIME })?;
+        let clicked = clicked?;
+
+        // Clicking the root window has no child
+        let frame = match clicked {
+            Some(frame) if !frame.is_none() => frame,
+            Some(_) => bail!("No window was clicked"),
+            None => bail!("Window selection was cancelled")
+        };
+
+        let xid = window::find_client(&conn, &atoms, frame)?.resource_id();
+        debug!(CAT, "Picked window {:#x}", xid);
+
+        Ok(xid)
+    }
+
+    // Sleeps in poll() like the watcher, so a timeout or unlock ends the wait instead of blocking start forever
+    // Returns the clicked child of the root for button 1, None for any other button
+    fn wait_for_click(&self, conn: &Connection) -> Result<Option<x::Window>> {
+        let (wake_tx, wake_rx) = match UnixStream::pair() {
+            Ok(pair) => pair,
+            Err(e) => bail!("Failed to create selection wake pipe: {}", e)
+        };
+        let _ = self.state.lock().unwrap().select_wake.insert(wake_tx);
+
+        let deadline = Instant::now() + SELECT_ON_START_TIMEOUT;
+        let clicked = loop {
+            match conn.poll_for_event() {
+                Ok(Some(xcb::Event::X(x::Event::ButtonPress(e)))) => break Ok((e.detail() == 1).then(|| e.child())),
+                Ok(Some(_)) => continue,
+                Ok(None) => {}
+                Err(e) => break Err(anyhow::anyhow!("Failed to wait for a click: {}", e))
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break Err(anyhow::anyhow!("No window was clicked within {:?}", SELECT_ON_START_TIMEOUT));
+            }
+
+            let mut fds = [
+                libc::pollfd { fd: conn.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: wake_rx.as_raw_fd(), events: libc::POLLIN, revents: 0 }
+            ];
+
+            // Rounded up so the last poll doesn't spin with a zero timeout
+            let timeout = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    break Err(anyhow::anyhow!("Failed to poll X connection: {}", e));
+                }
+            }
+
+            if fds[1].revents != 0 {
+                break Err(anyhow::anyhow!("Window selection was interrupted"));
+            }
+        };
+
+        self.state.lock().unwrap().select_wake.take();
+        clicked
+    }
+
+    // Root-relative position and size, for announcing a window without touching the capture state
+    fn window_geometry(&self, xid: Xid) -> Result<(i16, i16, u16, u16)> {
+        let state = self.state.lock().unwrap();
+        let conn = match state.connection.as_ref() {
+            Some(conn) => conn,
+            None => bail!("Not connected!")
+        };
+
+        let geometry = wait_for_reply(conn, conn.send_request(&GetGeometry {
+            drawable: Drawable::Window(xcb::XidNew::new(xid))
+        }))?;
+
+        let translated = wait_for_reply(conn, conn.send_request(&TranslateCoordinates {
+            src_window: xcb::XidNew::new(xid),
+            dst_window: geometry.root(),
+            src_x: 0,
+            src_y: 0
+        }))?;
+
+        Ok((translated.dst_x(), translated.dst_y(), geometry.width(), geometry.height()))
+    }
+
+    // Starts watching the window and announces it with window-bound
+    // Windows that weren't given as xid up front are also announced with window-found
+    fn bind_window(&self, xid: Xid) -> Result<()> {
+        if self.state.lock().unwrap().redirect_subwindows {
+            if let Err(e) = self.redirect_subwindows() {
+                warning!(CAT, "Failed to redirect subwindows, child content may be stale: {}", e);
+            }
+        }
+
+        if let Err(e) = self.set_backing_store() {
+            warning!(CAT, "Failed to set backing store, obscured regions may show other windows: {}", e);
+        }
+
+        // The root can't be redirected, and is never covered anyway
+        {
+            let mut state = self.state.lock().unwrap();
+            state.offscreen_stale = state.offscreen && !state.capture_root;
+        }
+
+        if self.state.lock().unwrap().reattach {
+            self.record_identity(xid);
+        }
+
+        self.start_watcher(xid)?;
+
+        let found = {
+            let mut state = self.state.lock().unwrap();
+            std::mem::take(&mut state.window_found_pending) || state.xid_resolved
+        };
+
+        if found {
+            match self.window_geometry(xid) {
+                Ok((x, y, width, height)) => {
+                    info!(CAT, imp: self, "Found window {:#x} at {}x{}+{}+{}", xid, width, height, x, y);
+                    self.obj().notify("xid");
+                    self.obj().emit_by_name::<()>("window-found", &[&xid, &(x as i32), &(y as i32), &(width as u32), &(height as u32)]);
+                }
+                Err(e) => warning!(CAT, "Failed to get the geometry of window {:#x}: {}", xid, e)
+            }
+        }
+
+        self.obj().emit_by_name::<()>("window-bound", &[&xid]);
+
+        Ok(())
+    }
+
+    // Only explicit windows need this, resolved ones are looked up with the selection properties again
+    fn record_identity(&self, xid: Xid) {
+        let mut state = self.state.lock().unwrap();
+        if state.xid_resolved {
+            return;
+        }
+
+        let atoms = match get_atoms(&mut state) {
+            Ok(atoms) => atoms,
+            Err(e) => {
+                warning!(CAT, "Failed to intern atoms, the window can't be reattached: {}", e);
+                return;
+            }
+        };
+        let conn = match state.connection.as_ref() {
+            Some(conn) => conn,
+            None => return
+        };
+
+        let window = xcb::XidNew::new(xid);
+        let identity = WindowIdentity {
+            class: window::get_class(conn, window).ok().flatten(),
+            pid: window::get_pid(conn, &atoms, window).ok().flatten()
+        };
+
+        if identity == WindowIdentity::default() {
+            warning!(CAT, "Window {:#x} has neither WM_CLASS nor _NET_WM_PID, it can't be reattached", xid);
+            state.bound_identity.take();
+        } else {
+            debug!(CAT, "Window {:#x} will be reattached by {:?}", xid, identity);
+            let _ = state.bound_identity.insert(identity);
+        }
+    }
+
+    // Client with the same class and PID, apps that recreate their toplevel keep both
+    fn find_recreated(&self, identity: &WindowIdentity) -> Result<Xid> {
+        let mut state = self.state.lock().unwrap();
+        let atoms = get_atoms(&mut state)?;
+        let conn = match state.connection.as_ref() {
+            Some(conn) => conn,
+            None => bail!("Not connected!")
+        };
+
+        let root = match conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize) {
+            Some(screen) => screen.root(),
+            None => bail!("Screen {:?} doesn't exist on the server", state.screen_num)
+        };
+
+        for candidate in window::client_list(conn, &atoms, root)? {
+            let class_matches = || identity.class.is_none() || window::get_class(conn, candidate).ok().flatten() == identity.class;
+            let pid_matches = || identity.pid.is_none() || window::get_pid(conn, &atoms, candidate).ok().flatten() == identity.pid;
+
+            if pid_matches() && class_matches() {
+                return Ok(candidate.resource_id());
+            }
+        }
+
+        bail!("No window matches {:?}", identity)
+    }
+
+    fn start_watcher(&self, xid: Xid) -> Result<()> {
+        // Connected here so a bad display or xauthority is reported instead of killing the thread
+        let (display, xauthority) = {
+            let state = self.state.lock().unwrap();
+            (state.display.clone(), state.xauthority.clone())
+        };
+        let conn = match xauth::connect(display.as_deref(), xauthority.as_deref(), &[xcb::Extension::Damage, xcb::Extension::Input, xcb::Extension::Present, xcb::Extension::RandR, xcb::Extension::XFixes]) {
+            Ok((conn, _)) => conn,
+            Err(e) => bail!("Failed to connect the window watcher: {}", e)
+        };
+
+        let run = Arc::new(AtomicBool::new(true));
+        let _  = self.state.lock().unwrap().resize_run.insert(run.clone());
+
+        let (wake_tx, wake_rx) = match UnixStream::pair() {
+            Ok(pair) => pair,
+            Err(e) => bail!("Failed to create watcher wake pipe: {}", e)
+        };
+        let _ = self.state.lock().unwrap().resize_wake.insert(wake_tx);
+
+        let state_arc = self.state.clone();
+
+        let mut state = self.state.lock().unwrap();
+        let _ = state.watcher_heartbeat.insert(Instant::now());
+        let _ = state.resize_handle.insert(thread::spawn(move || {
+            watch_window(state_arc, conn, xid, run, wake_rx);
+        }));
+
+        Ok(())
+    }
+
+    // Replaces a watcher that died or stopped responding, otherwise resizes and visibility changes go unnoticed
+    fn check_watcher(&self) -> Result<()> {
+        let xid = {
+            let state = self.state.lock().unwrap();
+            let finished = state.resize_handle.as_ref().is_some_and(|handle| handle.is_finished());
+            let silent = state.watcher_heartbeat.is_some_and(|heartbeat| heartbeat.elapsed() > WATCHER_HEARTBEAT_TIMEOUT);
+
+            match state.xid {
+                Some(xid) if finished || silent => xid,
+                _ => return Ok(())
+            }
+        };
+
+        warning!(CAT, "Window watcher stopped responding, restarting it");
+
+        // A stuck watcher can't be joined, it's told to stop and left to exit on its own
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(run) = state.resize_run.take() {
+                run.store(false, Ordering::SeqCst);
+            }
+            if let Some(mut wake) = state.resize_wake.take() {
+                let _ = wake.write_all(&[0]);
+            }
+            if let Some(handle) = state.resize_handle.take().filter(|handle| handle.is_finished()) {
+                if handle.join().is_err() {
+                    warning!(CAT, "Window watcher panicked");
+                }
+            }
+
+            // A failed restart is retried once the timeout passes again, not on every frame
+            let _ = state.watcher_heartbeat.insert(Instant::now());
+
+            // Events may have been missed while it was down
+            state.damage_active = false;
+            state.present_active = false;
+            state.damage_full = true;
+            state.needs_size_update = true;
+            state.needs_state_update = true;
+            state.watcher_restarts += 1;
+
+            let restarts = state.watcher_restarts;
+            state.stats.get_or_insert_with(|| gst::Structure::new_empty("ximageredux-stats")).set("watcher-restarts", restarts);
+        }
+
+        self.obj().notify("stats");
+
+        self.start_watcher(xid)
+    }
+
+    fn stop_watcher(&self) {
+        if let Some(run) = self.state.lock().unwrap().resize_run.take() {
+            run.store(false, Ordering::SeqCst);
+        }
+
+        // Wake the watcher out of its poll so it sees the stop flag right away
+        if let Some(mut wake) = self.state.lock().unwrap().resize_wake.take() {
+            let _ = wake.write_all(&[0]);
+        }
+
+        if let Some(handle) = self.state.lock().unwrap().resize_handle.take() {
+            if handle.join().is_err() {
+                warning!(CAT, "Window watcher panicked");
+            }
+        }
+        self.state.lock().unwrap().watcher_heartbeat.take();
+    }
+
+    // Looks for a new window matching the selection properties after the bound one was destroyed
+    // An explicit xid is only replaced with reattach, by a window with the same class and PID
+    // Returns whether a window is bound afterwards
+    fn rebind_window(&self) -> Result<bool> {
+        let identity = {
+            let mut state = self.state.lock().unwrap();
+            if !state.xid_resolved && state.xid.is_some() {
+                match state.bound_identity.take() {
+                    Some(identity) if state.reattach => {
+                        let _ = state.reattach_identity.insert(identity);
+                    }
+                    _ => return Ok(true)
+                }
+            }
+
+            if let Some(xid) = state.xid.take() {
+                debug!(CAT, "Window {:#x} was destroyed, looking for a replacement", xid);
+            }
+
+            state.xid_resolved = false;
+            // The server drops these along with the window, but not a named pixmap
+            state.subwindows_redirected = false;
+            state.window_redirected = false;
+            free_offscreen_pixmap(&mut state);
+            state.original_backing_store.take();
+            state.damage_active = false;
+            state.damage_full = true;
+            state.damage_rects.clear();
+            state.last_capture.take();
+            state.last_cursor_source.take();
+            state.needs_size_update = true;
+            state.needs_state_update = true;
+
+            state.reattach_identity.clone()
+        };
+
+        self.stop_watcher();
+
+        let found = match identity.as_ref() {
+            Some(identity) => self.find_recreated(identity).map(|xid| {
+                let mut state = self.state.lock().unwrap();
+                let _ = state.xid.insert(xid);
+                state.reattach_identity.take();
+                state.window_found_pending = true;
+            }),
+            None => self.resolve_window()
+        };
+
+        if let Err(e) = found {
+            trace!(CAT, "No replacement window yet: {}", e);
+            return Ok(false);
+        }
+
+        let xid = {
+            let mut state = self.state.lock().unwrap();
+            state.window_destroyed = false;
+            get_connection(&state)?.1
+        };
+
+        if identity.is_some() {
+            info!(CAT, imp: self, "Window was recreated as {:#x}, reattaching", xid);
+        }
+
+        self.bind_window(xid)?;
+
+        Ok(true)
+    }
+
+    fn active_window(&self) -> Result<Option<Xid>> {
+        let mut state = self.state.lock().unwrap();
+        let atoms = get_atoms(&mut state)?;
+        let conn = match state.connection.as_ref() {
+            Some(conn) => conn,
+            None => bail!("Not connected!")
+        };
+
+        let root = match conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize) {
+            Some(screen) => screen.root(),
+            None => bail!("Screen {:?} doesn't exist on the server", state.screen_num)
+        };
+
+        Ok(window::get_active_window(conn, &atoms, root)?.map(|window| window.resource_id()))
+    }
+
+    // Moves capture to the newly active window, keeping the current one when focus went nowhere
+    fn follow_active_window(&self) -> Result<()> {
+        self.state.lock().unwrap().active_window_changed = false;
+
+        let xid = match self.active_window()? {
+            Some(xid) if Some(xid) != self.state.lock().unwrap().xid => xid,
+            _ => return Ok(())
+        };
+
+        debug!(CAT, "Active window changed to {:#x}, retargeting", xid);
+
+        self.retarget(xid, true)
+    }
+
+    // Replaces an explicit xid with its toplevel, which also fails if the window doesn't exist
+    // Resolved windows come from the client list and are toplevels already
+    fn resolve_toplevel(&self) -> Result<()> {
+        let xid = {
+            let state = self.state.lock().unwrap();
+            match state.xid {
+                Some(xid) if !state.xid_resolved => xid,
+                _ => return Ok(())
+            }
+        };
+
+        let toplevel = self.toplevel_of(xid)?;
+        let _ = self.state.lock().unwrap().xid.insert(toplevel);
+
+        Ok(())
+    }
+
+    // Watches the root on a connection of its own so the events don't mix with anything else
+    fn start_waiting(&self) -> Result<()> {
+        let (display, xauthority) = {
+            let state = self.state.lock().unwrap();
+            (state.display.clone(), state.xauthority.clone())
+        };
+        let (conn, default_screen) = xauth::connect(display.as_deref(), xauthority.as_deref(), &[])?;
+        let screen_num = self.state.lock().unwrap().screen_num.unwrap_or(default_screen);
+
+        let root = match conn.get_setup().roots().nth(screen_num as usize) {
+            Some(screen) => screen.root(),
+            None => bail!("Screen {} doesn't exist on the server", screen_num)
+        };
+
+        conn.send_and_check_request(&ChangeWindowAttributes {
+            window: root,
+            value_list: &[Cw::EventMask(EventMask::SUBSTRUCTURE_NOTIFY | EventMask::PROPERTY_CHANGE)]
+        })?;
+
+        let mut state = self.state.lock().unwrap();
+        // A window resolved before it vanished again is looked up from scratch
+        if std::mem::take(&mut state.xid_resolved) {
+            state.xid.take();
+        }
+
+        let (width, height) = WAITING_FRAME_SIZE;
+        let _ = state.region.insert(Region { x: 0, y: 0, width, height });
+        let _ = state.root_watch.insert(conn);
+
+        Ok(())
+    }
+
+    // Looks for the window again whenever something changed on the root
+    // Returns whether it was found and bound
+    fn poll_for_window(&self) -> Result<bool> {
+        let changed = {
+            let state = self.state.lock().unwrap();
+            let conn = match state.root_watch.as_ref() {
+                Some(conn) => conn,
+                None => return Ok(true)
+            };
+
+            let mut changed = false;
+            loop {
+                match conn.poll_for_event() {
+                    Ok(Some(_)) => changed = true,
+                    Ok(None) => break,
+                    Err(xcb::Error::Protocol(_)) => {}
+                    Err(e) => bail!("Failed to poll for root events: {}", e)
+                }
+            }
+
+            changed
+        };
+
+        if !changed {
+            return Ok(false);
+        }
+
+        if let Err(e) = self.resolve_window().and_then(|_| self.resolve_toplevel()) {
+            trace!(CAT, "Window still doesn't exist: {}", e);
+            return Ok(false);
+        }
+
+        let xid = {
+            let mut state = self.state.lock().unwrap();
+            state.root_watch.take();
+            state.window_found_pending = true;
+            // The real size replaces the placeholder and renegotiates
+            state.region.take();
+            state.needs_size_update = true;
+            state.needs_state_update = true;
+            get_connection(&state)?.1
+        };
+
+        info!(CAT, imp: self, "Window {:#x} appeared, starting capture", xid);
+
+        self.bind_window(xid)?;
+
+        Ok(true)
+    }
+
+    // Transparent black in the negotiated format, flagged as a gap so downstream can tell it isn't content
+    fn blank_frame(&self) -> Result<CreateSuccess, gst::FlowError> {
+        let info = match self.state.lock().unwrap().video_info.clone() {
+            Some(info) => info,
+            None => {
+                error!(CAT, "Caps weren't negotiated before the first blank frame");
+                return Err(gst::FlowError::NotNegotiated);
+            }
+        };
+
+        let mut frame = match self.convert_output(gst::Buffer::from_mut_slice(vec![0u8; info.size()])) {
+            Ok(frame) => frame,
+            Err(e) => {
+                error!(CAT, "Failed to convert blank frame: {}", e);
+                return Err(gst::FlowError::Error);
+            }
+        };
+        frame.make_mut().set_flags(gst::BufferFlags::GAP);
+
+        self.set_last_frame_fresh(false);
+        Ok(CreateSuccess::NewBuffer(self.timestamp(frame)))
+    }
+
+    // Explicit xids can be children that toolkits report, which capture cropped or empty
+    fn toplevel_of(&self, xid: Xid) -> Result<Xid> {
+        let mut state = self.state.lock().unwrap();
+        let atoms = get_atoms(&mut state)?;
+        let conn = match state.connection.as_ref() {
+            Some(conn) => conn,
+            None => bail!("Not connected!")
+        };
+
+        let toplevel = window::find_toplevel(conn, &atoms, xcb::XidNew::new(xid), state.toplevel_mode)?.resource_id();
+        if toplevel != xid {
+            debug!(CAT, "Window {:#x} isn't a toplevel, capturing {:#x} instead", xid, toplevel);
+        }
+
+        Ok(toplevel)
+    }
+
+    // Switches capture to another window without stopping, the old window is left as it was found
+    fn retarget(&self, xid: Xid, resolved: bool) -> Result<()> {
+        self.stop_watcher();
+
+        // The old window is still around, so it gets its attributes back
+        if let Err(e) = self.release_server_resources() {
+            warning!(CAT, "Failed to release X resources of the previous window: {}", e);
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            let _ = state.xid.insert(xid);
+            state.xid_resolved = resolved;
+            state.window_destroyed = false;
+            state.damage_active = false;
+            state.damage_full = true;
+            state.damage_rects.clear();
+            state.last_capture.take();
+            state.last_cursor_source.take();
+            // Picks up the new geometry and renegotiates on this frame
+            state.needs_size_update = true;
+            state.needs_state_update = true;
+        }
+
+        self.bind_window(xid)
+    }
+
+    // Uses its own connection so scanning doesn't hold up capture
+    fn scan_windows(&self) -> Result<gst::Array> {
+        let (display, xauthority, screen) = {
+            let state = self.state.lock().unwrap();
+            (state.display.clone(), state.xauthority.clone(), state.screen)
+        };
+        let (conn, default_screen) = xauth::connect(display.as_deref(), xauthority.as_deref(), &[])?;
+
+        // The screen property applies like it does for capture, the display's default screen otherwise
+        let screen_num = if screen >= 0 { screen } else { default_screen };
+
+        Ok(gst::Array::new(window::scan_screen(&conn, screen_num)?.into_iter().map(|info| {
+            let (instance, class) = info.class.unzip();
+
+            gst::Structure::builder("window")
+                .field("xid", info.xid)
+                .field("title", info.title)
+                .field("instance", instance)
+                .field("class", class)
+                .field("x", info.x as i32)
+                .field("y", info.y as i32)
+                .field("width", info.width as u32)
+                .field("height", info.height as u32)
+                .field("visibility", info.visibility)
+                .field("fullscreen", info.fullscreen)
+                .build()
+                .to_send_value()
+        })))
+    }
+
+    // Latest frame with its caps, for applications that want a still of the window
+    pub(super) fn snapshot(&self) -> Option<gst::Sample> {
+        let state = self.state.lock().unwrap();
+        let buf = state.last_frame.as_ref()?;
+        let caps = state.output_info.as_ref().or(state.video_info.as_ref())?.to_caps().ok()?;
+
+        Some(gst::Sample::builder().buffer(buf).caps(&caps).build())
+    }
+
+    // Latest frame scaled down to fit max_dimension, for previews that don't warrant a scaling pipeline
+    fn thumbnail(&self, max_dimension: u32) -> Result<Option<gst::Sample>> {
+        let (buf, info) = {
+            let state = self.state.lock().unwrap();
+            match (state.last_frame.clone(), state.output_info.clone().or(state.video_info.clone())) {
+                (Some(buf), Some(info)) => (buf, info),
+                _ => return Ok(None)
+            }
+        };
+
+        let largest = info.width().max(info.height());
+        if max_dimension == 0 || largest <= max_dimension {
+            return Ok(self.snapshot());
+        }
+
+        let format_info = info.format_info();
+        if format_info.n_planes() != 1 || format_info.bits() != 8 {
+            bail!("Thumbnails of {} frames aren't supported", info.format());
+        }
+
+        let width = (info.width() as u64 * max_dimension as u64 / largest as u64).max(1) as u32;
+        let height = (info.height() as u64 * max_dimension as u64 / largest as u64).max(1) as u32;
+
+        let map = buf.map_readable()?;
+        let data = thumbnail::downscale(
+            map.as_slice(),
+            map.len() / info.height() as usize,
+            info.width() as usize,
+            info.height() as usize,
+            format_info.pixel_stride()[0] as usize,
+            width as usize,
+            height as usize
+        );
+
+        let thumbnail_info = gst_video::VideoInfo::builder(info.format(), width, height)
+            .fps(info.fps())
+            .build()?;
+
+        let mut thumbnail = gst::Buffer::from_mut_slice(data);
+        {
+            let thumbnail = thumbnail.get_mut().unwrap();
+            thumbnail.set_pts(buf.pts());
+            thumbnail.set_duration(buf.duration());
+        }
+
+        Ok(Some(gst::Sample::builder().buffer(&thumbnail).caps(&thumbnail_info.to_caps()?).build()))
+    }
+
+    // Keeps last_frame current while PAUSED, since create isn't called then
+    fn start_paused_capture(&self) {
+        let (tx, rx) = mpsc::channel::<()>();
+        let weak = self.obj().downgrade();
+
+        let handle = thread::spawn(move || {
+            // Sender is dropped to stop, which disconnects the channel
+            while let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(PAUSED_CAPTURE_INTERVAL) {
+                let Some(obj) = weak.upgrade() else { break };
+                let imp = obj.imp();
+
+                match imp.get_frame().and_then(|frame| imp.convert_output(frame)) {
+                    Ok(frame) => {
+                        let _ = imp.state.lock().unwrap().last_frame.insert(frame);
+                    }
+                    Err(e) => trace!(CAT, "Failed to capture while paused: {}", e)
+                }
+            }
+        });
+
+        let mut state = self.state.lock().unwrap();
+        let _ = state.paused_capture_stop.insert(tx);
+        let _ = state.paused_capture_handle.insert(handle);
+    }
+
+    fn stop_paused_capture(&self) {
+        let handle = {
+            let mut state = self.state.lock().unwrap();
+            state.paused_capture_stop.take();
+            state.paused_capture_handle.take()
+        };
+
+        // The thread only refreshes the last frame, so its panic is reported rather than taking down the state change
+        if let Some(Err(panic)) = handle.map(|handle| handle.join()) {
+            let reason = panic.downcast_ref::<&str>().copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+
+            gst::element_imp_error!(self, gst::CoreError::Thread, ["Paused capture thread panicked: {}", reason]);
+        }
+    }
+
+    // The cursor shape at cursor-scale, or scaled to the screen's DPI when that's 0
+    fn fetch_cursor_image(&self) -> Option<CursorImage> {
+        let scale = {
+            let state = self.state.lock().unwrap();
+            if state.cursor_scale > 0.0 { state.cursor_scale } else { state.cursor_auto_scale }
+        };
+
+        self.fetch_cursor_shape().map(|shape| if scale == 1.0 { shape } else { shape.scaled(scale) })
+    }
+
+    // Custom image, then XFixes, then the fallback, at the size it comes in
+    fn fetch_cursor_shape(&self) -> Option<CursorImage> {
+        let (xfixes_available, fallback, custom) = {
+            let state = self.state.lock().unwrap();
+            (state.xfixes_available, state.cursor_fallback, state.custom_cursor.clone())
+        };
+
+        if custom.is_some() {
+            return custom;
+        }
+
+        if !xfixes_available {
+            return match fallback {
+                CursorFallback::Synthetic => Some(CursorImage::synthetic()),
+                CursorFallback::None => None
+            };
+        }
+
+        // The shape only changes with a CursorNotify, fetching it every frame is wasted round-trips at high framerates
+        {
+            let state = self.state.lock().unwrap();
+            if state.cursor_tracking {
+                if let Some(shape) = state.cursor_shape.clone() {
+                    return Some(shape);
+                }
+            }
+        }
+
+        // Works now that open_connection negotiates the XFixes version before any other request
+        let res = {
+            let state = self.state.lock().unwrap();
+            get_connection(&state).and_then(|(conn, _)| wait_for_reply(conn, conn.send_request(&xcb::xfixes::GetCursorImage {})))
+        };
+
+        match res {
+            Ok(reply) => {
+                let shape = CursorImage {
+                    width: reply.width(),
+                    height: reply.height(),
+                    xhot: reply.xhot(),
+                    yhot: reply.yhot(),
+                    pixels: reply.cursor_image().to_vec()
+                };
+
+                let _ = self.state.lock().unwrap().cursor_shape.insert(shape.clone());
+                Some(shape)
+            }
+            Err(e) => {
+                warning!(CAT, "Failed to get cursor image: {}", e);
+                match fallback {
+                    CursorFallback::Synthetic => Some(CursorImage::synthetic()),
+                    CursorFallback::None => None
+                }
+            }
+        }
+    }
+
+    // Composites the cursor at pos into a copy of frame
+    // With capture-cursor-only-when-moving, a still pointer reuses the cached cursor (or the whole last frame)
+    fn draw_cursor(&self, mut frame: gst::Buffer, pos: Position) -> gst::Buffer {
+        let (throttle, moved, same_source, cached) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.cursor_only_when_moving,
+                state.last_cursor_position != Some(pos),
+                state.last_cursor_source.as_ref().is_some_and(|src| src.as_ptr() == frame.as_ptr()),
+                state.cached_cursor.clone()
+            )
+        };
+
+        if throttle && !moved && same_source {
+            if let Some(last) = self.state.lock().unwrap().last_frame.clone() {
+                return last;
+            }
+        }
+
+        let cursor = match cached.filter(|_| throttle && !moved) {
+            Some(cursor) => cursor,
+            None => match self.fetch_cursor_image() {
+                Some(cursor) => cursor,
+                None => return frame
+            }
+        };
+
+        let source = frame.clone();
+        if let Err(e) = self.composite_cursor(&mut frame, &pos, &cursor) {
+            error!(CAT, "Failed to draw cursor: {}", e);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let _ = state.last_cursor_position.insert(pos);
+        let _ = state.last_cursor_source.insert(source);
+        let _ = state.cached_cursor.insert(cursor);
+
+        frame
+    }
+
+    // cursor-image wins over cursor-image-location, neither set means the real cursor is drawn
+    fn load_custom_cursor(&self) -> Result<()> {
+        let (sample, location, xhot, yhot) = {
+            let state = self.state.lock().unwrap();
+            (state.cursor_image.clone(), state.cursor_image_location.clone(), state.cursor_hotspot_x as u16, state.cursor_hotspot_y as u16)
+        };
+
+        let custom = match (sample, location) {
+            (Some(sample), _) => Some(cursor::from_sample(&sample, xhot, yhot)?),
+            (None, Some(location)) => Some(cursor::load_custom(&location, xhot, yhot)?),
+            (None, None) => None
+        };
+
+        self.state.lock().unwrap().custom_cursor = custom;
+        Ok(())
+    }
+
+    // Ring to draw around the pointer while the last click's highlight is fading
+    fn click_ring(&self) -> Option<CursorImage> {
+        let state = self.state.lock().unwrap();
+        let elapsed = state.last_click.filter(|_| state.highlight_clicks)?.elapsed();
+
+        (elapsed < CLICK_HIGHLIGHT_DURATION)
+            .then(|| CursorImage::click_ring(1.0 - elapsed.as_secs_f32() / CLICK_HIGHLIGHT_DURATION.as_secs_f32()))
+    }
+
+    // Attaches the cursor at pos as overlay composition meta, leaving the pixels untouched
+    fn attach_cursor_overlay(&self, mut frame: gst::Buffer, pos: Position) -> gst::Buffer {
+        let ring = self.click_ring();
+        let cursor = self.fetch_cursor_image();
+
+        let images: Vec<&CursorImage> = ring.iter().chain(cursor.iter()).collect();
+        if images.is_empty() {
+            return frame;
+        }
+
+        match cursor::overlay(&images, pos.x.into(), pos.y.into()) {
+            Ok(composition) => {
+                gst_video::VideoOverlayCompositionMeta::add(frame.make_mut(), &composition);
+            }
+            Err(e) => error!(CAT, "Failed to build cursor overlay: {}", e)
+        }
+
+        frame
+    }
+
+    // Converts to the negotiated YUV format, frames are passed through when output-format is native
+    fn convert_output(&self, frame: gst::Buffer) -> Result<gst::Buffer> {
+        let (info, output_info, colorimetry) = {
+            let state = self.state.lock().unwrap();
+            match (state.video_info.clone(), state.output_info.clone()) {
+                (Some(info), Some(output_info)) => (info, output_info, state.colorimetry),
+                _ => return Ok(frame)
+            }
+        };
+
+        yuv::convert(&frame, &info, &output_info, colorimetry)
+    }
+
+    fn composite_cursor(&self, frame: &mut gst::Buffer, pos: &Position, cursor: &CursorImage) -> Result<()> {
+        let info = match self.state.lock().unwrap().video_info.clone() {
+            Some(info) => info,
+            None => bail!("Caps are not negotiated yet!")
+        };
+
+        let buf = frame.make_mut();
+        let mut map = buf.map_writable()?;
+        let stride = map.len() / info.height() as usize;
+
+        if !cursor::blend(map.as_mut_slice(), stride, &info, cursor, pos.x.into(), pos.y.into()) {
+            trace!(CAT, "Format {} doesn't support cursor blending, skipping", info.format());
+        }
+
+        Ok(())
+    }
+
+    // Whether cursor-mode allows drawing once the pointer is known to be over the capture region
+    // Windows are compared by their frame, so the client, its frame and nested windows all count as the captured window
+    fn cursor_mode_allows(&self) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let mode = state.cursor_mode;
+        match mode {
+            CursorMode::Always => return Ok(true),
+            CursorMode::Never => return Ok(false),
+            _ => {}
+        }
+
+        let atoms = get_atoms(&mut state)?;
+        let (conn, xid) = get_connection(&state)?;
+        let root = match conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize) {
+            Some(screen) => screen.root(),
+            None => bail!("Screen {:?} doesn't exist on the server", state.screen_num)
+        };
+
+        // Everything is inside the root and it can't lose focus
+        if xid == root.resource_id() {
+            return Ok(true);
+        }
+
+        let other = match mode {
+            // The root's child under the pointer is the topmost toplevel there
+            CursorMode::OnlyWhenInsideWindow => Some(wait_for_reply(conn, conn.send_request(&QueryPointer { window: root }))?.child())
+                .filter(|child| !child.is_none()),
+            CursorMode::OnlyWhenWindowFocused => window::get_active_window(conn, &atoms, root)?,
+            CursorMode::Always | CursorMode::Never => unreachable!()
+        };
+
+        let other = match other {
+            Some(other) => other,
+            None => return Ok(false)
+        };
+
+        let own_frame = window::find_toplevel(conn, &atoms, xcb::XidNew::new(xid), ToplevelMode::Frame)?;
+        Ok(window::find_toplevel(conn, &atoms, other, ToplevelMode::Frame)? == own_frame)
+    }
+
+    // Returns the relative position of the cursor in the window if it's in the window region
+    // Along with its pressed buttons, bit 0 for button 1
+    fn cursor_is_in_bounds(&self) -> Result<Option<(Position, u32)>> {
+        let state = self.state.lock().unwrap();
+        let (conn, xid) = get_connection(&state)?;
+        let win = xcb::XidNew::new(xid);
+
+        if state.position.is_none() || state.size.is_none() {
+            bail!("No position/size set!");
+        }
+
+        // The core pointer is whichever device moved last, multi-seat setups need XInput2 to follow a specific one
+        let (same_screen, root, win_x, win_y, buttons) = if state.pointer_device >= 0 && state.xinput_available {
+            let cookie = conn.send_request(&xcb::xinput::XiQueryPointer {
+                window: win,
+                device: xcb::xinput::Device::from_id(state.pointer_device as u16)
+            });
+
+            // Coordinates are 16.16 fixed point, the cursor is drawn at the pixel containing the sub-pixel position
+            let reply = wait_for_reply(conn, cookie)?;
+            // Bit n is button n, so the unused bit 0 is shifted out
+            let buttons = reply.buttons().first().map_or(0, |mask| mask >> 1);
+            (reply.same_screen(), reply.root(), reply.win_x() >> 16, reply.win_y() >> 16, buttons)
+        } else {
+            let cookie = conn.send_request(&QueryPointer {
+                window: win
+            });
+
+            let reply = wait_for_reply(conn, cookie)?;
+            // Button 1 to 5 are bits 8 to 12 of the core mask
+            let buttons = (reply.mask().bits() >> 8) & 0x1F;
+            (reply.same_screen(), reply.root(), reply.win_x() as i32, reply.win_y() as i32, buttons)
+        };
+
+        // With multiple screens (Zaphod) the pointer can be on another root, where win_x/win_y are meaningless
+        // Each screen is its own coordinate space with no layout relative to the others, so there's nothing to map
+        // the position into: a pointer on another root can't be over this window, and the cursor comes back with it
+        if !same_screen {
+            let screen = conn.get_setup().roots().position(|screen| screen.root() == root);
+            trace!(CAT, "Pointer is on screen {:?} (root {:#x}), not the captured one", screen, root.resource_id());
+            return Ok(None);
+        }
+
+        let region = match state.region {
+            Some(region) => region,
+            None => bail!("No capture region set!")
+        };
+
+        // win_x/win_y are relative to the window right now, so they stay correct even if the cached position is stale
+        let x = win_x - region.x as i32;
+        let y = win_y - region.y as i32;
+        let bounds_match = x >= 0 && y >= 0 && x < region.width as i32 && y < region.height as i32;
+
+        Ok(if bounds_match {
+            Some((Position {
+                x: x as i16,
+                y: y as i16,
+            }, buttons))
+        } else { None })
+    }
+}
+
+// Pointer as a 1x1 region of interest of type "pointer", with an "ximageredux/pointer" param carrying x, y and buttons
+// ROI metas are understood by 1.18 consumers, where GstCustomMeta would need 1.20
+fn attach_pointer_meta(frame: &mut gst::Buffer, pos: Position, buttons: u32) {
+    remove_roi_metas(frame, "pointer");
+
+    let mut meta = gst_video::VideoRegionOfInterestMeta::add(frame.make_mut(), "pointer", (pos.x as u32, pos.y as u32, 1, 1));
+    meta.add_param(gst::Structure::builder("ximageredux/pointer")
+        .field("x", i32::from(pos.x))
+        .field("y", i32::from(pos.y))
+        .field("buttons", buttons)
+        .build());
+}
+
+// Each changed rectangle as a "damage" region of interest in frame coordinates, none at all when nothing changed
+fn attach_damage_meta(frame: &mut gst::Buffer, rects: &[x::Rectangle], region: Region) {
+    remove_roi_metas(frame, "damage");
+
+    let buf = frame.make_mut();
+    for rect in rects {
+        // Already clipped to the region, so the offsets can't go negative
+        let x = (rect.x as i32 - region.x as i32) as u32;
+        let y = (rect.y as i32 - region.y as i32) as u32;
+        gst_video::VideoRegionOfInterestMeta::add(buf, "damage", (x, y, rect.width.into(), rect.height.into()));
+    }
+}
+
+// Reused frames still carry the metas of the frame they were first pushed as
+fn remove_roi_metas(frame: &mut gst::Buffer, roi_type: &str) {
+    frame.make_mut().foreach_meta_mut(|mut meta| {
+        let stale = meta.downcast_ref::<gst_video::VideoRegionOfInterestMeta>().is_some_and(|roi| roi.roi_type() == roi_type);
+        std::ops::ControlFlow::Continue(if stale { gst::buffer::BufferMetaForeachAction::Remove } else { gst::buffer::BufferMetaForeachAction::Keep })
+    });
+}
+
+fn connection_context(shared: &SharedConnection) -> gst::Context {
+    let mut context = gst::Context::new(CONNECTION_CONTEXT_TYPE, true);
+    context.get_mut().unwrap().structure_mut().set("connection", shared.clone());
+    context
+}
+
+// Formats sharing a memory layout with the native one, differing only in whether the padding byte is alpha
+fn format_alternatives(native: gst_video::VideoFormat) -> Vec<gst_video::VideoFormat> {
+    use gst_video::VideoFormat::*;
+
+    let alternative = match native {
+        Bgrx => Some(Bgra),
+        Bgra => Some(Bgrx),
+        Rgbx => Some(Rgba),
+        Rgba => Some(Rgbx),
+        Xrgb => Some(Argb),
+        Argb => Some(Xrgb),
+        Xbgr => Some(Abgr),
+        Abgr => Some(Xbgr),
+        _ => None
+    };
+
+    std::iter::once(native).chain(alternative).collect()
+}
+
+// GLMemory can't be drawn into or converted on the CPU, so the cursor is attached as an overlay instead
+fn gl_output(state: &State) -> bool {
+    #[cfg(feature = "gl")]
+    {
+        state.gl.negotiated
+    }
+    #[cfg(not(feature = "gl"))]
+    {
+        let _ = state;
+        false
+    }
+}
+
+// Negotiated format if downstream picked an alpha variant of a native format without alpha
+fn alpha_fill_format(state: &State) -> Option<gst_video::VideoFormatInfo> {
+    // Padding bytes are undefined, so they have to be made opaque if downstream asked for alpha
+    match (state.native_format, state.video_info.as_ref()) {
+        (Some(native), Some(info)) if info.format() != native && info.has_alpha() && !gst_video::VideoFormatInfo::from_format(native).has_alpha() => Some(info.format_info()),
+        _ => None
+    }
+}
+
+// Clips damage to the capture region and merges it into a bounding box if there's too much to grab separately
+fn coalesce_damage(rects: Vec<x::Rectangle>, region: Region) -> Vec<x::Rectangle> {
+    let clipped: Vec<_> = rects.into_iter().filter_map(|rect| {
+        let x1 = rect.x.max(region.x);
+        let y1 = rect.y.max(region.y);
+        let x2 = (rect.x as i32 + rect.width as i32).min(region.x as i32 + region.width as i32);
+        let y2 = (rect.y as i32 + rect.height as i32).min(region.y as i32 + region.height as i32);
+
+        (x2 > x1 as i32 && y2 > y1 as i32).then(|| x::Rectangle {
+            x: x1,
+            y: y1,
+            width: (x2 - x1 as i32) as u16,
+            height: (y2 - y1 as i32) as u16
+        })
+    }).collect();
+
+    if clipped.len() <= MAX_DAMAGE_REGIONS {
+        return clipped;
+    }
+
+    let x1 = clipped.iter().map(|r| r.x).min().unwrap();
+    let y1 = clipped.iter().map(|r| r.y).min().unwrap();
+    let x2 = clipped.iter().map(|r| r.x as i32 + r.width as i32).max().unwrap();
+    let y2 = clipped.iter().map(|r| r.y as i32 + r.height as i32).max().unwrap();
+
+    vec![x::Rectangle { x: x1, y: y1, width: (x2 - x1 as i32) as u16, height: (y2 - y1 as i32) as u16 }]
+}
+
+// Copies packed rows into a buffer whose stride is a multiple of align, described by a GstVideoMeta
+fn align_rows(data: &[u8], info: &gst_video::VideoInfo, align: usize) -> Result<gst::Buffer> {
+    let height = info.height() as usize;
+    let src_stride = data.len() / height;
+    let row_len = info.width() as usize * info.format_info().pixel_stride()[0] as usize;
+    let stride = row_len.div_ceil(align) * align;
+
+    let mut aligned = vec![0u8; stride * height];
+    for (dst, src) in aligned.chunks_exact_mut(stride).zip(data.chunks_exact(src_stride)) {
+        dst[..row_len].copy_from_slice(&src[..row_len]);
+    }
+
+    let mut buf = gst::Buffer::from_mut_slice(aligned);
+    add_video_meta(buf.get_mut().unwrap(), info, stride)?;
+
+    Ok(buf)
+}
+
+fn add_video_meta(buf: &mut gst::BufferRef, info: &gst_video::VideoInfo, stride: usize) -> Result<()> {
+    gst_video::VideoMeta::add_full(
+        buf,
+        gst_video::VideoFrameFlags::empty(),
+        info.format(),
+        info.width(),
+        info.height(),
+        &[0],
+        &[stride as i32]
+    )?;
+
+    Ok(())
+}
+
+fn fill_alpha(data: &mut [u8], info: &gst_video::VideoFormatInfo) {
+    let pixel_stride = info.pixel_stride()[0] as usize;
+    let alpha_offset = info.poffset()[3] as usize;
+
+    for pixel in data.chunks_exact_mut(pixel_stride) {
+        pixel[alpha_offset] = 0xFF;
+    }
+}
+
+// One frame at the negotiated framerate, or the minimum frame interval for variable framerates
+fn frame_interval(state: &State) -> gst::ClockTime {
+    let interval = [state.frame_duration, state.min_frame_interval]
+        .into_iter()
+        .find(|interval| !interval.is_zero())
+        .unwrap_or(Duration::from_millis(DEFAULT_MIN_FRAME_INTERVAL_MS as u64));
+
+    gst::ClockTime::from_nseconds(interval.as_nanos() as u64)
+}
+
+// Drops everything captured at the cached depth when the window's current depth differs from it
+// The depth is then read again with the format, returns whether it had changed
+fn forget_changed_depth(state: &mut State, depth: u8) -> bool {
+    if state.depth.is_none_or(|cached| cached == depth) {
+        return false;
+    }
+
+    state.depth.take();
+    state.damage_full = true;
+    state.last_capture.take();
+    if state.flush_on_resize {
+        state.last_frame.take();
+    }
+
+    true
+}
+
+// One frame at the negotiated framerate, or the gap between the last two frames at a variable one
+fn expected_duration(state: &State) -> gst::ClockTime {
+    if state.frame_duration.is_zero() {
+        state.last_pts_delta.filter(|delta| !delta.is_zero()).unwrap_or_else(|| frame_interval(state))
+    } else {
+        gst::ClockTime::from_nseconds(state.frame_duration.as_nanos() as u64)
+    }
+}
+
+// Crop rectangle clamped to the window, the whole window if the crop doesn't overlap it
+// Also returns whether clamping changed the requested crop
+fn capture_region(state: &State) -> Option<(Region, bool)> {
+    let size = state.size?;
+
+    // Decorations are removed first, window crop coordinates are then relative to the client area
+    // Extents that don't fit the window are ignored, e.g. while the window manager is still reparenting
+    // Shadows are drawn inside the client, so both add up when a decorated frame holds a CSD client
+    let extents = [
+        state.frame_extents.filter(|_| state.crop_decorations),
+        state.csd_extents.filter(|_| state.crop_csd_shadows)
+    ].into_iter().flatten().fold(FrameExtents::default(), |total, e| FrameExtents {
+        left: total.left + e.left,
+        right: total.right + e.right,
+        top: total.top + e.top,
+        bottom: total.bottom + e.bottom
+    });
+    let extents = Some(extents)
+        .filter(|e| e.left + e.right < size.width as u32 && e.top + e.bottom < size.height as u32)
+        .unwrap_or_default();
+    let bounds = Region {
+        x: extents.left as i16,
+        y: extents.top as i16,
+        width: size.width - (extents.left + extents.right) as u16,
+        height: size.height - (extents.top + extents.bottom) as u16
+    };
+
+    // Monitors are in root coordinates, which are the window coordinates when capturing the root
+    let bounds = match state.monitor {
+        Some(monitor) => {
+            let x1 = bounds.x.max(monitor.x);
+            let y1 = bounds.y.max(monitor.y);
+            let x2 = (bounds.x as i32 + bounds.width as i32).min(monitor.x as i32 + monitor.width as i32);
+            let y2 = (bounds.y as i32 + bounds.height as i32).min(monitor.y as i32 + monitor.height as i32);
+
+            if x2 > x1 as i32 && y2 > y1 as i32 {
+                Region { x: x1, y: y1, width: (x2 - x1 as i32) as u16, height: (y2 - y1 as i32) as u16 }
+            } else {
+                bounds
+            }
+        }
+        None => bounds
+    };
+    let (bx2, by2) = (bounds.x as i32 + bounds.width as i32, bounds.y as i32 + bounds.height as i32);
+
+    let (x, y) = match state.crop_coordinates {
+        CropCoordinates::Window => (bounds.x as i32 + state.crop_x, bounds.y as i32 + state.crop_y),
+        // Position comes from TranslateCoordinates against the root
+        CropCoordinates::Root => {
+            let position = state.position?;
+            (state.crop_x - position.x as i32, state.crop_y - position.y as i32)
+        }
+    };
+
+    let x2 = if state.crop_width == 0 { bx2 } else { (x + state.crop_width as i32).min(bx2) };
+    let y2 = if state.crop_height == 0 { by2 } else { (y + state.crop_height as i32).min(by2) };
+    let x1 = x.max(bounds.x as i32);
+    let y1 = y.max(bounds.y as i32);
+
+    if x2 <= x1 || y2 <= y1 {
+        warning!(CAT, "Crop doesn't overlap the window, capturing the whole window");
+        return Some((bounds, true));
+    }
+
+    let clamped = x < bounds.x as i32 || y < bounds.y as i32 ||
+        (state.crop_width != 0 && x + state.crop_width as i32 > bx2) ||
+        (state.crop_height != 0 && y + state.crop_height as i32 > by2);
+
+    Some((Region { x: x1 as i16, y: y1 as i16, width: (x2 - x1) as u16, height: (y2 - y1) as u16 }, clamped))
+}
+
+// Instance and class are matched without case, WM_CLASS class names are usually capitalized
+fn class_matches(wanted: &str, instance: &str, class: &str) -> bool {
+    match wanted.split_once(',') {
+        Some((wanted_instance, wanted_class)) => {
+            wanted_instance.trim().eq_ignore_ascii_case(instance) && wanted_class.trim().eq_ignore_ascii_case(class)
+        }
+        None => wanted.eq_ignore_ascii_case(instance) || wanted.eq_ignore_ascii_case(class)
+    }
+}
+
+// Whether there's anything to resolve a window from
+fn has_selection(state: &State) -> bool {
+    state.xid.is_some()
+        || state.capture_root
+        || state.select_on_start
+        || state.follow_active_window
+        || state.window_type != WindowType::Any
+        || state.window_name.is_some()
+        || state.window_class.is_some()
+        || state.window_pid != 0
+}
+
+// Selection properties that are set, for errors when nothing matches
+fn describe_selection(state: &State) -> String {
+    let mut criteria = vec![format!("window-type {:?}", state.window_type)];
+    if let Some(name) = state.window_name.as_deref() {
+        criteria.push(format!("window-name {:?} ({:?})", name, state.name_match));
+    }
+    if let Some(class) = state.window_class.as_deref() {
+        criteria.push(format!("window-class {:?}", class));
+    }
+    if state.window_pid != 0 {
+        criteria.push(format!("window-pid {}", state.window_pid));
+    }
+
+    criteria.join(", ")
+}
+
+// window-name prepared once per resolution
+enum NameMatcher<'a> {
+    Exact(&'a str),
+    // Lowercased
+    Substring(String),
+    Regex(regex::Regex)
+}
+
+impl<'a> NameMatcher<'a> {
+    fn new(mode: NameMatch, name: &'a str) -> Result<Self> {
+        Ok(match mode {
+            NameMatch::Exact => Self::Exact(name),
+            NameMatch::Substring => Self::Substring(name.to_lowercase()),
+            NameMatch::Regex => match regex::Regex::new(name) {
+                Ok(regex) => Self::Regex(regex),
+                Err(e) => bail!("window-name isn't a valid regular expression: {}", e)
+            }
+        })
+    }
+
+    fn matches(&self, candidate: &str) -> bool {
+        match self {
+            Self::Exact(name) => candidate == *name,
+            Self::Substring(name) => candidate.to_lowercase().contains(name.as_str()),
+            Self::Regex(regex) => regex.is_match(candidate)
+        }
+    }
+}
+
+// Includes the error code, sequence number, opcodes, and the name of the request that caused it
+fn log_x_error(connection: &str, e: &xcb::ProtocolError) {
+    warning!(CAT, "X error on the {} connection: {:?}", connection, e);
+}
+
+// Whether an error came from the X server and matches the given core protocol error
+fn is_x_error(e: &anyhow::Error, pred: impl Fn(&x::Error) -> bool) -> bool {
+    matches!(e.downcast_ref::<xcb::Error>(), Some(xcb::Error::Protocol(xcb::ProtocolError::X(e, _))) if pred(e))
+}
+
+// How many rows of the region fit in a single GetImage given the server's maximum request length
+fn rows_per_request(conn: &Connection, fmt: PixmapFormat, size: Region) -> u16 {
+    let max_bytes = conn.get_maximum_request_length() as usize * 4;
+    let pad = fmt.scanline_pad.max(8) as usize;
+    let stride = (size.width as usize * fmt.bits_per_pixel as usize).div_ceil(pad) * pad / 8;
+
+    if stride == 0 || stride * size.height as usize <= max_bytes {
+        size.height
+    } else {
+        (max_bytes / stride).clamp(1, size.height as usize) as u16
+    }
+}
+
+// Sends every strip's GetImage before waiting so the strips arrive back to back
+// Errors are passed through unchanged so callers can inspect the X error
+fn get_image_strips(conn: &Connection, drawable: Drawable, region: Region, rows_per_strip: u16) -> Result<Vec<x::GetImageReply>> {
+    let rows_per_strip = rows_per_strip.max(1);
+
+    let cookies: Vec<_> = (0..region.height).step_by(rows_per_strip as usize).map(|y| conn.send_request(&GetImage {
+        format: x::ImageFormat::ZPixmap, // jpg
+        drawable,
+        x: region.x,
+        y: region.y + y as i16,
+        width: region.width,
+        height: rows_per_strip.min(region.height - y),
+        plane_mask: u32::MAX,
+    })).collect();
+
+    let mut replies = Vec::with_capacity(cookies.len());
+    for cookie in cookies {
+        replies.push(conn.wait_for_reply(cookie)?);
+    }
+
+    Ok(replies)
+}
+
+fn wait_for_reply<C>(conn: &Connection, cookie: C) -> Result<C::Reply> 
+    where C: CookieWithReplyChecked 
+    {
+        match conn.wait_for_reply(cookie) {
+            Ok(reply) => Ok(reply),
+            Err(e) => bail!("Failed to wait for X reply: {}", e)
+        }
+}
+
+fn get_atoms(state: &mut MutexGuard<State>) -> Result<Atoms> {
+    if let Some(atoms) = state.atoms {
+        return Ok(atoms);
+    }
+
+    let atoms = match state.connection.as_ref() {
+        Some(conn) => Atoms::intern(conn)?,
+        None => bail!("Not connected!")
+    };
+
+    Ok(*state.atoms.insert(atoms))
+}
+
+// What frames are read from, and the offset of the window's origin in it
+fn capture_drawable(state: &State, xid: Xid) -> (Drawable, i16) {
+    match state.offscreen_pixmap {
+        Some((pixmap, border)) => (Drawable::Pixmap(pixmap), border),
+        None => (Drawable::Window(xcb::XidNew::new(xid)), 0)
+    }
+}
+
+// The pixmap outlives the window, so it's freed whenever the window is let go of
+fn free_offscreen_pixmap(state: &mut State) {
+    if let (Some((pixmap, _)), Some(conn)) = (state.offscreen_pixmap.take(), state.connection.as_ref()) {
+        conn.send_request(&x::FreePixmap { pixmap });
+    }
+}
+
+// Whether screen is set and one of the server's roots, -1 means the display's default
+fn screen_exists(conn: &Connection, screen: i32) -> bool {
+    screen >= 0 && (screen as usize) < conn.get_setup().roots().count()
+}
+
+fn get_connection<'a>(state: &'a MutexGuard<State>) -> Result<(&'a xcb::Connection, Xid)> {
+    let xid = match state.xid {
+        Some(xid) => xid,
+        None => bail!("XID is not set!"),
+    };
+
+    Ok((state.connection.as_ref().unwrap(), xid))
+}
+
+// Reports every damaged rectangle of the window to the watcher connection
+fn create_damage(conn: &Connection, xid: Xid) -> Result<xcb::damage::Damage> {
+    if !conn.active_extensions().any(|ext| ext == xcb::Extension::Damage) {
+        bail!("Damage extension is unavailable");
+    }
+
+    let cookie = conn.send_request(&xcb::damage::QueryVersion {
+        client_major_version: 1,
+        client_minor_version: 1
+    });
+    wait_for_reply(conn, cookie)?;
+
+    let damage = conn.generate_id();
+    conn.send_and_check_request(&xcb::damage::Create {
+        damage,
+        drawable: Drawable::Window(xcb::XidNew::new(xid)),
+        level: xcb::damage::ReportLevel::RawRectangles
+    })?;
+
+    Ok(damage)
+}
+
+// Asks for a CompleteNotify every time the window presents a frame
+fn create_present_listener(conn: &Connection, xid: Xid) -> Result<xcb::present::EventXid> {
+    if !conn.active_extensions().any(|ext| ext == xcb::Extension::Present) {
+        bail!("Present extension is unavailable");
+    }
+
+    let cookie = conn.send_request(&xcb::present::QueryVersion {
+        major_version: 1,
+        minor_version: 0
+    });
+    wait_for_reply(conn, cookie)?;
+
+    let eid = conn.generate_id();
+    conn.send_and_check_request(&xcb::present::SelectInput {
+        eid,
+        window: xcb::XidNew::new(xid),
+        event_mask: xcb::present::EventMask::COMPLETE_NOTIFY
+    })?;
+
+    Ok(eid)
+}
+
+// Watches the target window for changes on a dedicated connection until run is cleared
+// Sleeps in poll() on the connection so events and stop requests (via wake) are handled immediately
+fn watch_window(state_arc: Arc<Mutex<State>>, conn: Connection, xid: Xid, run: Arc<AtomicBool>, wake: UnixStream) {
+    conn.send_request(&ChangeWindowAttributes {
+        window: xcb::XidNew::new(xid),
+        value_list: &[Cw::EventMask(EventMask::STRUCTURE_NOTIFY | EventMask::PROPERTY_CHANGE)]
+    });
+
+    // Focus changes show up as _NET_ACTIVE_WINDOW changes on the root
+    let active_window = {
+        let mut state = state_arc.lock().unwrap();
+        let root = conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize).map(|screen| screen.root());
+
+        match (state.follow_active_window, root) {
+            (true, Some(root)) => match get_atoms(&mut state) {
+                Ok(atoms) => Some((root, atoms.net_active_window)),
+                Err(e) => {
+                    warning!(CAT, "Failed to intern atoms, not following the active window: {}", e);
+                    None
+                }
+            }
+            _ => None
+        }
+    };
+
+    if let Some((root, _)) = active_window {
+        conn.send_request(&ChangeWindowAttributes {
+            window: root,
+            value_list: &[Cw::EventMask(EventMask::PROPERTY_CHANGE)]
+        });
+    }
+
+    // The root doesn't always resize when outputs are rearranged, CRTC changes move the monitor crop too
+    let watch_monitors = {
+        let state = state_arc.lock().unwrap();
+        state.capture_root && state.randr_available && (state.monitor_name.is_some() || state.monitor_index >= 0)
+    };
+
+    if watch_monitors {
+        conn.send_request(&xcb::randr::SelectInput {
+            window: xcb::XidNew::new(xid),
+            enable: xcb::randr::NotifyMask::SCREEN_CHANGE | xcb::randr::NotifyMask::CRTC_CHANGE
+        });
+    }
+
+    // Cursor shape changes are screen-wide, so they're selected on the root
+    let track_cursor = {
+        let state = state_arc.lock().unwrap();
+        let root = conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize).map(|screen| screen.root());
+        root.filter(|_| state.show_cursor && state.xfixes_available)
+    };
+
+    if let Some(root) = track_cursor {
+        // XFixes has to be told the version on every connection
+        let res = conn.wait_for_reply(conn.send_request(&xcb::xfixes::QueryVersion {
+            client_major_version: 4,
+            client_minor_version: 0
+        })).and_then(|_| conn.send_and_check_request(&xcb::xfixes::SelectCursorInput {
+            window: root,
+            event_mask: xcb::xfixes::CursorNotifyMask::DISPLAY_CURSOR
+        }).map_err(xcb::Error::from));
+
+        match res {
+            Ok(()) => {
+                let mut state = state_arc.lock().unwrap();
+                // Whatever was cached may have changed while nobody was listening
+                state.cursor_shape.take();
+                state.cursor_tracking = true;
+            }
+            Err(e) => warning!(CAT, "Failed to track cursor changes, fetching the cursor every frame: {}", e)
+        }
+    }
+
+    // Core ButtonPress on the root can only be selected by one client, raw XInput events reach everyone
+    let track_clicks = {
+        let state = state_arc.lock().unwrap();
+        let root = conn.get_setup().roots().nth(state.screen_num.unwrap_or(0) as usize).map(|screen| screen.root());
+        root.filter(|_| state.highlight_clicks && state.xinput_available)
+    };
+
+    if let Some(root) = track_clicks {
+        let res = conn.wait_for_reply(conn.send_request(&xcb::xinput::XiQueryVersion {
+            major_version: 2,
+            minor_version: 0
+        })).and_then(|_| conn.send_and_check_request(&xcb::xinput::XiSelectEvents {
+            window: root,
+            masks: &[xcb::xinput::EventMaskBuf::new(xcb::xinput::Device::AllMaster, &[xcb::xinput::XiEventMask::RAW_BUTTON_PRESS])]
+        }).map_err(xcb::Error::from));
+
+        if let Err(e) = res {
+            warning!(CAT, "Failed to listen for clicks, not highlighting them: {}", e);
+        }
+    }
+
+    let mut damage = None;
+    if state_arc.lock().unwrap().use_damage {
+        match create_damage(&conn, xid) {
+            Ok(d) => {
+                let _ = damage.insert(d);
+                state_arc.lock().unwrap().damage_active = true;
+            }
+            Err(e) => warning!(CAT, "Failed to set up damage tracking, grabbing every frame: {}", e)
+        }
+    }
+
+    let mut present = None;
+    if state_arc.lock().unwrap().capture_backend == CaptureBackend::Present {
+        match create_present_listener(&conn, xid) {
+            Ok(eid) => {
+                let _ = present.insert(eid);
+                state_arc.lock().unwrap().present_active = true;
+            }
+            Err(e) => warning!(CAT, "Failed to listen for presented frames, falling back to get-image: {}", e)
+        }
+    }
+
+    // VERY IMPORTANT
+    if let Err(e) = conn.flush() {
+        error!(CAT, "Failed to set up the window watcher, stopping it: {}", e);
+        let mut state = state_arc.lock().unwrap();
+        state.cursor_tracking = false;
+        state.damage_active = false;
+        state.present_active = false;
+        return;
+    }
+
+    let mut last_size = None;
+
+    while run.load(Ordering::SeqCst) {
+        let _ = state_arc.lock().unwrap().watcher_heartbeat.insert(Instant::now());
+
+        // Drain everything xcb has already queued before sleeping on the socket
+        loop {
+            match conn.poll_for_event() {
+                Ok(Some(xcb::Event::X(e))) => match e {
+                    // Listen for size changes
+                    ConfigureNotify(e) => {
+                        let size = Size { width: e.width(), height: e.height() };
+
+                        // Don't send window relocation events (size stays the same)
+                        // Unless the crop is relative to the root, then moving the window moves the crop
+                        if let Some(last_size) = last_size.as_ref() {
+                            if *last_size == size && state_arc.lock().unwrap().crop_coordinates != CropCoordinates::Root {
+                                continue;
+                            }
+                        } else {
+                            let _ = last_size.insert(size);
+                        }
+
+                        state_arc.lock().unwrap().needs_size_update = true;
+                    }
+                    DestroyNotify(_) => {
+                        state_arc.lock().unwrap().window_destroyed = true;
+                    }
+                    // Mapping allocates a new pixmap, the named one keeps the content from before the unmap
+                    MapNotify(_) => {
+                        let mut state = state_arc.lock().unwrap();
+                        state.offscreen_stale = state.offscreen;
+                    }
+                    PropertyNotify(e) if active_window.is_some_and(|(root, atom)| e.window() == root && e.atom() == atom) => {
+                        state_arc.lock().unwrap().active_window_changed = true;
+                    }
+                    // Properties never change geometry
+                    PropertyNotify(e) => {
+                        let mut state = state_arc.lock().unwrap();
+                        state.needs_state_update = true;
+                        let _ = state.x_server_time.insert((e.time(), Instant::now()));
+                    }
+                    _ => {}
+                },
+                Ok(Some(xcb::Event::Damage(xcb::damage::Event::Notify(e)))) => {
+                    let mut state = state_arc.lock().unwrap();
+                    state.damage_rects.push(e.area());
+                    state.needs_content_update = true;
+                    let _ = state.x_server_time.insert((e.timestamp(), Instant::now()));
+                }
+                Ok(Some(xcb::Event::XFixes(xcb::xfixes::Event::CursorNotify(_)))) => {
+                    state_arc.lock().unwrap().cursor_shape.take();
+                }
+                // Buttons 4 to 7 are scrolling, not clicks
+                Ok(Some(xcb::Event::Input(xcb::xinput::Event::RawButtonPress(e)))) if !(4..=7).contains(&e.detail()) => {
+                    let _ = state_arc.lock().unwrap().last_click.insert(Instant::now());
+                }
+                Ok(Some(xcb::Event::RandR(_))) => {
+                    state_arc.lock().unwrap().needs_size_update = true;
+                }
+                Ok(Some(xcb::Event::Present(xcb::present::Event::CompleteNotify(e)))) => {
+                    // MSC notifications don't carry new content
+                    if e.kind() == xcb::present::CompleteKind::Pixmap {
+                        let present_frame = state_arc.lock().unwrap().present_frame.clone();
+                        let (presented, cvar) = &*present_frame;
+                        *presented.lock().unwrap() = true;
+                        cvar.notify_all();
+                    }
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(xcb::Error::Protocol(e)) if state_arc.lock().unwrap().log_x_errors => log_x_error("watcher", &e),
+                Err(e) => {
+                    error!(CAT, "Failed to poll for X event: {e}");
+                    break;
+                }
+            }
+        }
+
+        let mut fds = [
+            libc::pollfd { fd: conn.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: wake.as_raw_fd(), events: libc::POLLIN, revents: 0 }
+        ];
+
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, WATCHER_POLL_TIMEOUT_MS) } < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                error!(CAT, "Failed to poll X connection: {e}");
+                thread::sleep(Duration::from_millis(WATCHER_POLL_TIMEOUT_MS as u64));
+            }
+        }
+    }
+
+    // Without notifications a cached shape could go stale
+    state_arc.lock().unwrap().cursor_tracking = false;
+
+    if let Some(damage) = damage {
+        if let Err(e) = conn.send_and_check_request(&xcb::damage::Destroy { damage }) {
+            warning!(CAT, "Failed to destroy damage object: {}", e);
+        }
+    }
+
+    if let Some(eid) = present {
+        state_arc.lock().unwrap().present_active = false;
+
+        if let Err(e) = conn.send_and_check_request(&xcb::present::SelectInput {
+            eid,
+            window: xcb::XidNew::new(xid),
+            event_mask: xcb::present::EventMask::NO_EVENT
+        }) {
+            warning!(CAT, "Failed to stop listening for presented frames: {}", e);
+        }
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for XImageRedux {
+    const NAME: &'static str = "XImageRedux";
+    type Type = super::XImageRedux;
+    type ParentType = PushSrc;
+}
+
+impl PushSrcImpl for XImageRedux {
+    fn create(
+            &self,
+            _buffer: Option<&mut gst::BufferRef>,
+        ) -> Result<CreateSuccess, gst::FlowError> {
+        if self.state.lock().unwrap().eos_requested {
+            return self.finish_stream();
+        }
+
+        #[cfg(feature = "test-inject")]
+        if let Some(size) = self.state.lock().unwrap().injected_size {
+            return self.create_injected(size);
+        }
+
+        // A still is a single frame followed by EOS
+        {
+            let state = self.state.lock().unwrap();
+            if state.still_image && state.still_frame_sent {
+                return Err(gst::FlowError::Eos);
+            }
+        }
+
+        // Never produce frames faster than the minimum interval, regardless of negotiated framerate
+        let wait = {
+            let state = self.state.lock().unwrap();
+            state.last_create_instant
+                .map(|last| state.min_frame_interval.saturating_sub(last.elapsed()))
+                .unwrap_or_default()
+        };
+
+        if !wait.is_zero() {
+            let clock = self.obj().clock().unwrap_or_else(gst::SystemClock::obtain);
+            let now = clock.time().unwrap_or_default();
+            self.wait_on_clock(&clock, now + gst::ClockTime::from_nseconds(wait.as_nanos() as u64))?;
+        }
+
+        let _ = self.state.lock().unwrap().last_create_instant.insert(Instant::now());
+
+        // Wait until the next frame is due at the negotiated framerate, in running time
+        let frame_duration = {
+            let state = self.state.lock().unwrap();
+            gst::ClockTime::from_nseconds(state.frame_duration.as_nanos() as u64)
+        };
+
+        if !frame_duration.is_zero() {
+            let last_time = self.state.lock().unwrap().last_frame_time;
+            if let (Some(last_time), Some(now)) = (last_time, self.running_time()) {
+                let due = last_time + frame_duration;
+                let obj = self.obj();
+                if now < due {
+                    if let (Some(clock), Some(base_time)) = (obj.clock(), obj.base_time()) {
+                        self.wait_on_clock(&clock, base_time + due)?;
+                    }
+                }
+            }
+
+            // Frames stay on the framerate's grid unless capture fell more than a frame behind
+            if let Some(now) = self.running_time() {
+                let mut state = self.state.lock().unwrap();
+                let due = state.last_frame_time.map(|last_time| last_time + frame_duration).filter(|due| now < *due + frame_duration);
+                let _ = state.last_frame_time.insert(due.unwrap_or(now));
+            }
+        }
+        
+        if let Err(e) = self.check_watcher() {
+            warning!(CAT, "Failed to restart window watcher: {}", e);
+        }
+
+        match self.poll_for_window() {
+            Ok(true) => {}
+            Ok(false) => return self.blank_frame(),
+            Err(e) => {
+                error!(CAT, "Failed to wait for the window: {}", e);
+                return Err(gst::FlowError::Error);
+            }
+        }
+
+        let pending_xid = self.state.lock().unwrap().pending_xid.take();
+        if let Some(xid) = pending_xid.filter(|xid| Some(*xid) != self.state.lock().unwrap().xid) {
+            debug!(CAT, "xid changed to {:#x}, retargeting", xid);
+
+            if let Err(e) = self.toplevel_of(xid).and_then(|xid| self.retarget(xid, false)) {
+                error!(CAT, "Failed to switch to window {:#x}: {}", xid, e);
+                return Err(gst::FlowError::Error);
+            }
+        }
+
+        if self.state.lock().unwrap().active_window_changed {
+            if let Err(e) = self.follow_active_window() {
+                warning!(CAT, "Failed to follow the active window: {}", e);
+            }
+        }
+
+        // Rebinds to a relaunched window when the target was found by resolve_window
+        if self.state.lock().unwrap().window_destroyed {
+            match self.rebind_window() {
+                Ok(true) => {}
+                Ok(false) => {
+                    let last = self.state.lock().unwrap().last_frame.clone();
+                    return match last {
+                        Some(buf) => {
+                            self.set_last_frame_fresh(false);
+                            Ok(CreateSuccess::NewBuffer(self.timestamp(buf)))
+                        }
+                        None => Err(gst::FlowError::Error)
+                    };
+                }
+                Err(e) => {
+                    error!(CAT, "Failed to rebind window: {}", e);
+                    return Err(gst::FlowError::Error);
+                }
+            }
+        }
+
+        // Updates size
+        match self.update_size_if_needed() {
+            Ok(did_update_size) => if did_update_size {
+                if let Err(e) = self.negotiate() {
+                    error!(CAT, "Failed to renegotiate after resize: {}", e.to_string());
+                    return Err(gst::FlowError::Error);
+                }
+            }
+            Err(e) => {
+                error!(CAT, "Failed to resize: {}", e.to_string());
+                return Err(gst::FlowError::Error);
+            }
+        }
+
+        // Set by the size update when the window was hidden with stop-on-hidden
+        if self.state.lock().unwrap().eos_requested {
+            return self.finish_stream();
+        }
+
+        self.announce_initial_state();
+
+        if self.state.lock().unwrap().log_x_errors {
+            self.drain_x_errors();
+        }
+
+        self.wait_for_present();
+
+        // Get a frame
+        let mut frame = match self.get_frame() {
+            Ok(f) => {
+                self.state.lock().unwrap().consecutive_fallbacks = 0;
+                f
+            }
+            Err(e) => {
+                let mut state = self.state.lock().unwrap();
+
+                // Don't let a persistent failure look like a frozen stream forever
+                state.consecutive_fallbacks += 1;
+                if state.frame_repeat_limit > 0 && state.consecutive_fallbacks > state.frame_repeat_limit {
+                    let count = state.consecutive_fallbacks;
+                    drop(state);
+
+                    gst::element_imp_error!(self, gst::ResourceError::Read, ["Failed to capture {} frames in a row: {}", count, e]);
+                    return Err(FlowError::Error);
+                }
+
+                // If failed to get frame, try to use the last one as a temporary measure
+                if let Some(buf) = state.last_frame.clone() {
+                    drop(state);
+                    trace!(CAT, "Failed to get frame, but last frame is usable.");
+                    self.set_last_frame_fresh(false);
+                    return Ok(CreateSuccess::NewBuffer(self.timestamp(buf)));
+                } else {
+                    error!(CAT, "Failed to get frame: {}", e.to_string());
+                    return Err(FlowError::Error);
+                }
+            }
+        };
+
+        // Copy cursor in if needed, an overlay is attached after conversion so it isn't lost with the input buffer
+        let mut overlay_pos = None;
+        let (show_cursor, cursor_overlay, pointer_meta) = {
+            let state = self.state.lock().unwrap();
+            (state.show_cursor && state.cursor_mode != CursorMode::Never, state.cursor_overlay || gl_output(&state), state.pointer_meta)
+        };
+
+        let pointer = if show_cursor || pointer_meta {
+            match self.cursor_is_in_bounds() {
+                Ok(pointer) => pointer,
+                Err(e) => {
+                    error!(CAT, "Failed to get cursor position: {}", e.to_string());
                     return Err(gst::FlowError::Error);
                 }
             }
+        } else {
+            None
+        };
+
+        let show_cursor = show_cursor && pointer.is_some() && self.cursor_mode_allows().unwrap_or_else(|e| {
+            warning!(CAT, "Failed to check cursor-mode, drawing the cursor: {}", e);
+            true
+        });
+
+        if let Some((pos, _)) = pointer.filter(|_| show_cursor) {
+            if cursor_overlay {
+                overlay_pos = Some(pos);
+            } else {
+                // Under the cursor, so the pointer stays visible
+                if let Some(ring) = self.click_ring() {
+                    if let Err(e) = self.composite_cursor(&mut frame, &pos, &ring) {
+                        error!(CAT, "Failed to draw click highlight: {}", e);
+                    }
+                }
+
+                frame = self.draw_cursor(frame, pos);
+            }
+        }
+
+        let frame = match self.convert_output(frame) {
+            Ok(frame) => frame,
+            Err(e) => {
+                error!(CAT, "Failed to convert frame: {}", e);
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        let mut frame = match overlay_pos {
+            Some(pos) => self.attach_cursor_overlay(frame, pos),
+            None => frame
+        };
+
+        if let Some((pos, buttons)) = pointer.filter(|_| pointer_meta) {
+            attach_pointer_meta(&mut frame, pos, buttons);
+        }
+
+        let damage = {
+            let mut state = self.state.lock().unwrap();
+            state.frame_damage.take().filter(|_| state.damage_meta).zip(state.region)
+        };
+
+        if let Some((rects, region)) = damage {
+            attach_damage_meta(&mut frame, &rects, region);
+        }
+
+        let frame = self.timestamp(frame);
+
+        // Set this frame as last
+        {
+            let mut state = self.state.lock().unwrap();
+            let _ = state.last_frame.insert(frame.clone());
+            if state.still_image {
+                state.still_frame_sent = true;
+            }
         }
 
-        // Set this frame as last
-        let _ = self.state.lock().unwrap().last_frame.insert(frame.clone());
+        self.set_last_frame_fresh(true);
 
         Ok(CreateSuccess::NewBuffer(frame))
     }
@@ -391,16 +3880,30 @@ impl PushSrcImpl for XImageRedux {
 
 impl BaseSrcImpl for XImageRedux {
     fn caps(&self, _filter: Option<&gst::Caps>) -> Option<gst::Caps> {
+        // Injected frames are BGRx, the most common X visual
+        #[cfg(feature = "test-inject")]
+        if let Some(size) = self.state.lock().unwrap().injected_size {
+            return Some(gst::Caps::builder("video/x-raw")
+                .field("format", gst_video::VideoFormat::Bgrx.to_str())
+                .field("width", size.width as i32)
+                .field("height", size.height as i32)
+                .field("framerate", gst::FractionRange::new(gst::Fraction::new(0, 1), gst::Fraction::new(i32::MAX, 1)))
+                .build());
+        }
+
         if self.state.lock().unwrap().connection.is_none() {
             if let Err(e) = self.open_connection() {
                 error!(CAT, "Failed to open connection: {}", e);
-                return Some(self.obj().pad_template_list().iter().next().unwrap().caps().copy())
+                return Some(self.template_caps())
             }
         }
 
-        if let Err(e) = self.update_size_if_needed() {
-            error!(CAT, "Failed to update size: {}", e.to_string());
-            return None;
+        // There's no window to measure while waiting, the placeholder region is used instead
+        if self.state.lock().unwrap().root_watch.is_none() {
+            if let Err(e) = self.update_size_if_needed() {
+                error!(CAT, "Failed to update size, falling back to template caps: {}", e);
+                return Some(self.template_caps());
+            }
         }
 
         let fmt = match unsafe { self.get_video_format() } {
@@ -411,30 +3914,160 @@ impl BaseSrcImpl for XImageRedux {
             }
         };
 
-        let c_str: &CStr = unsafe { CStr::from_ptr(gst_video_format_to_string(fmt)) };
+        let native: gst_video::VideoFormat = unsafe { from_glib(fmt) };
 
-        let state = self.state.lock().unwrap();
-        let size = state.size.as_ref().unwrap();
+        let mut state = self.state.lock().unwrap();
+        let _ = state.native_format.insert(native);
+        let size = match state.region.as_ref() {
+            Some(size) => size,
+            None => {
+                error!(CAT, "Window size is unknown, falling back to template caps");
+                return Some(self.template_caps());
+            }
+        };
+
+        let yuv_format = match state.output_format {
+            OutputFormat::Native => None,
+            OutputFormat::I420 => Some(gst_video::VideoFormat::I420),
+            OutputFormat::Nv12 => Some(gst_video::VideoFormat::Nv12)
+        };
+
+        let caps = match yuv_format {
+            Some(format) if cursor::rgb_offsets(&gst_video::VideoFormatInfo::from_format(native)).is_some() => gst::Caps::builder("video/x-raw")
+                .field("format", format.to_str())
+                .field("colorimetry", match state.colorimetry {
+                    Colorimetry::Bt601 => "bt601",
+                    Colorimetry::Bt709 => "bt709"
+                }),
+            _ => {
+                if yuv_format.is_some() {
+                    warning!(CAT, "Can't convert {} to YUV, advertising the native format", native);
+                }
+
+                // Native format first so it's picked unless downstream prefers an equivalent
+                gst::Caps::builder("video/x-raw")
+                    .field("format", gst::List::new(format_alternatives(native).iter().map(|f| f.to_str())))
+            }
+        };
+
+        let caps = caps
+            .field("width", size.width as i32)
+            .field("height", size.height as i32)
+            .field("pixel-aspect-ratio", state.pixel_aspect_ratio);
+
+        // Stills are advertised as 0/1, which image encoders like pngenc accept as a single picture
+        let caps = if state.still_image {
+            caps.field("framerate", gst::Fraction::new(0, 1))
+        } else {
+            caps.field("framerate", gst::FractionRange::new(gst::Fraction::new(0, 1), gst::Fraction::new(i32::MAX, 1)))
+        }.build();
 
-        Some(gst::Caps::builder("video/x-raw")
-            .field("format", &c_str.to_str().unwrap())
-            .field("width", &(size.width as i32))
-            .field("height", &(size.height as i32))
-            .field("framerate", &(gst::FractionRange::new(gst::Fraction::new(0, 1), gst::Fraction::new(i32::MAX, 1))))
-            .build())
+        // Only the Composite pixmap can be bound as a texture, capturing on screen stays in system memory
+        #[cfg(feature = "gl")]
+        let caps = if state.offscreen && state.composite_available { gl::with_memory_caps(caps) } else { caps };
+
+        Some(caps)
     }
 
+    // Only records negotiated values, so it's fine for this to run before the connection is opened
     fn set_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
-        if self.state.lock().unwrap().connection.is_none() {
-            return Err(gst::LoggableError::new(*CAT, glib::BoolError::new("Not ready!", "imp.rs", "set_caps", 0)));
-        }
-
         let framerate: gst::Fraction = match caps.structure(0).unwrap().value("framerate").unwrap().get() {
             Ok(f) => f,
-            Err(e) => return Err(gst::LoggableError::new(*CAT, glib::BoolError::new(format!("Error: {}", e.to_string()), "imp.rs", "set_caps", 0)))
+            Err(e) => return Err(gst::LoggableError::new(*CAT, glib::BoolError::new(format!("Error: {}", e), "imp.rs", "set_caps", 0)))
+        };
+
+        let info = match gst_video::VideoInfo::from_caps(caps) {
+            Ok(info) => info,
+            Err(e) => return Err(gst::LoggableError::new(*CAT, e))
+        };
+
+        // 0/1 is variable framerate, frames are captured as fast as min-frame-interval-ms allows
+        // Nanoseconds so framerates above 1000fps don't round down to variable as well
+        let mut state = self.state.lock().unwrap();
+        state.frame_duration = if framerate.numer() <= 0 || framerate.denom() <= 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos(1_000_000_000 * framerate.denom() as u64 / framerate.numer() as u64)
+        };
+
+        #[cfg(feature = "gl")]
+        {
+            let negotiated = gl::is_memory_caps(caps);
+            if negotiated && !state.offscreen {
+                return Err(gst::loggable_error!(CAT, "GLMemory can only be produced with offscreen enabled"));
+            }
+
+            // A capture in the other kind of memory can't be reused as the next frame
+            if negotiated != state.gl.negotiated {
+                state.last_capture.take();
+            }
+            state.gl.negotiated = negotiated;
+        }
+
+        // YUV caps are only offered with output-format, frames are still captured in the native format
+        if info.format_info().is_yuv() {
+            let native = match state.native_format {
+                Some(native) => native,
+                None => return Err(gst::loggable_error!(CAT, "Negotiated {} before the native format was known", info.format()))
+            };
+
+            let capture_info = match gst_video::VideoInfo::builder(native, info.width(), info.height()).fps(info.fps()).par(info.par()).build() {
+                Ok(capture_info) => capture_info,
+                Err(e) => return Err(gst::LoggableError::new(*CAT, e))
+            };
+
+            let _ = state.video_info.insert(capture_info);
+            let _ = state.output_info.insert(info);
+        } else {
+            let _ = state.video_info.insert(info);
+            state.output_info.take();
+        }
+
+        Ok(())
+    }
+
+    // Answers other elements looking for a connection to share
+    fn query(&self, query: &mut gst::QueryRef) -> bool {
+        if let gst::QueryViewMut::Context(q) = query.view_mut() {
+            if q.context_type() == CONNECTION_CONTEXT_TYPE {
+                let state = self.state.lock().unwrap();
+                if let (Some(connection), Some(screen_num)) = (state.connection.clone(), state.screen_num) {
+                    q.set_context(&connection_context(&SharedConnection { connection, screen_num, display: state.display.clone() }));
+                    return true;
+                }
+            }
+
+            #[cfg(feature = "gl")]
+            if self.state.lock().unwrap().gl.handle_query(self.obj().upcast_ref(), q) {
+                return true;
+            }
+        }
+
+        BaseSrcImplExt::parent_query(self, query)
+    }
+
+    // GLMemory only comes from this element's own pool, whatever downstream proposed is replaced
+    #[cfg(feature = "gl")]
+    fn decide_allocation(&self, query: &mut gst::query::Allocation) -> Result<(), gst::LoggableError> {
+        if !self.state.lock().unwrap().gl.negotiated {
+            return self.parent_decide_allocation(query);
+        }
+
+        let caps = match query.get_owned().0 {
+            Some(caps) => caps,
+            None => return Err(gst::loggable_error!(CAT, "Allocation query without caps"))
         };
 
-        self.state.lock().unwrap().frame_duration = Duration::from_millis(1000 * framerate.denom() as u64 / framerate.numer() as u64);
+        let (pool, size) = match self.prepare_gl(&caps) {
+            Ok(pool) => pool,
+            Err(e) => return Err(gst::loggable_error!(CAT, "Failed to set up GL output: {}", e))
+        };
+
+        if query.allocation_pools().is_empty() {
+            query.add_allocation_pool(Some(&pool), size, 0, 0);
+        } else {
+            query.set_nth_allocation_pool(0, Some(&pool), size, 0, 0);
+        }
 
         Ok(())
     }
@@ -450,11 +4083,58 @@ impl BaseSrcImpl for XImageRedux {
     }
 
     fn start(&self) -> Result<(), gst::ErrorMessage> {
-        if let Err(e) = self.open_connection() {
+        #[cfg(feature = "test-inject")]
+        if self.state.lock().unwrap().injected_size.is_some() {
+            return Ok(());
+        }
+
+        // Normally opened at NULL->READY, but negotiation or a lost connection can leave it unset
+        if self.state.lock().unwrap().connection.is_none() {
+            if let Err(e) = self.open_connection() {
+                return Err(error_msg!(
+                    gst::ResourceError::Failed,
+                    [&e.to_string()]
+                ))
+            }
+        }
+
+        {
+            let state = self.state.lock().unwrap();
+            let screens = state.connection.as_ref().map_or(0, |conn| conn.get_setup().roots().count());
+            if state.screen >= 0 && state.screen as usize >= screens {
+                return Err(error_msg!(
+                    gst::ResourceError::NotFound,
+                    ["Screen {} doesn't exist on the server, it has {}", state.screen, screens]
+                ))
+            }
+        }
+
+        if let Err(e) = self.load_custom_cursor() {
             return Err(error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to load custom cursor: {}", e]
+            ))
+        }
+
+        if let Err(e) = self.resolve_window().and_then(|_| self.resolve_toplevel()) {
+            let wait = {
+                let state = self.state.lock().unwrap();
+                state.wait_for_window && has_selection(&state)
+            };
+
+            if !wait {
+                return Err(error_msg!(
+                    gst::ResourceError::NotFound,
+                    [&e.to_string()]
+                ))
+            }
+
+            info!(CAT, imp: self, "Waiting for the window to appear: {}", e);
+
+            return self.start_waiting().map_err(|e| error_msg!(
                 gst::ResourceError::Failed,
                 [&e.to_string()]
-            ))
+            ));
         }
 
         let xid = {
@@ -462,79 +4142,144 @@ impl BaseSrcImpl for XImageRedux {
             get_connection(&state_wrap).unwrap().1
         };
 
-        let run = Arc::new(AtomicBool::new(true));
-        let _  = self.state.lock().unwrap().resize_run.insert(run.clone());
+        if let Err(e) = self.bind_window(xid) {
+            return Err(error_msg!(
+                gst::ResourceError::Failed,
+                [&e.to_string()]
+            ))
+        }
 
-        let state_arc = self.state.clone();
+        Ok(())
+    }
 
-        let _ = self.state.lock().unwrap().resize_handle.insert(thread::spawn(move || {
-            let conn = xcb::Connection::connect(None).unwrap().0;
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.eos_requested = false;
+            state.final_frame_sent = false;
+            state.still_frame_sent = false;
+            state.consecutive_fallbacks = 0;
+            state.last_frame_time.take();
+            state.last_pts.take();
+            state.last_pts_delta.take();
+            state.benchmark_window = BenchmarkWindow::default();
+            state.initial_state_announced = false;
+            state.damage_active = false;
+            state.damage_full = true;
+            state.damage_rects.clear();
+            state.last_capture.take();
+            state.last_cursor_position.take();
+            state.last_cursor_source.take();
+            state.cached_cursor.take();
+            state.cursor_shape.take();
+            #[cfg(feature = "gl")]
+            state.gl.stop();
+        }
 
-            conn.send_request(&ChangeWindowAttributes {
-                window: unsafe { xcb::XidNew::new(xid) },
-                value_list: &[Cw::EventMask(EventMask::STRUCTURE_NOTIFY | EventMask::PROPERTY_CHANGE)]
-            });
+        self.stop_watcher();
 
-            // VERY IMPORTANT
-            conn.flush().unwrap();
+        if let Err(e) = self.release_server_resources() {
+            warning!(CAT, "Failed to release X resources: {}", e);
+        }
 
-            let mut last_size = None;
+        let mut state = self.state.lock().unwrap();
+        if std::mem::take(&mut state.xid_resolved) {
+            state.xid.take();
+        }
+        state.window_destroyed = false;
+        state.active_window_changed = false;
+        state.bound_identity.take();
+        state.reattach_identity.take();
+        state.window_found_pending = false;
 
-            while run.load(Ordering::SeqCst) {
-                match conn.poll_for_event() {
-                    Ok(e) => if let Some(ev) = e {
-                        if let xcb::Event::X(e) = ev {
-                            match e {
-                                // Listen for size changes
-                                ConfigureNotify(e) => {
-                                    let size = Size { width: e.width().into(), height: e.height().into() };
-
-                                    // Don't send window relocation events (size stays the same)
-                                    if let Some(last_size) = last_size.as_ref() {
-                                        if *last_size == size {
-                                            continue;
-                                        }
-                                    } else {
-                                        let _ = last_size.insert(size);
-                                    }
-
-                                    state_arc.lock().unwrap().needs_size_update = true;
-                                }
-                                PropertyNotify(_) => {
-                                    state_arc.lock().unwrap().needs_size_update = true;
-                                }
-                                _ => {}
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        error!(CAT, "Failed to poll for X event: {e}");
-                    }
-                }
+        // Drops the placeholder region along with the root watch
+        if state.root_watch.take().is_some() {
+            state.region.take();
+        }
 
-                thread::sleep(Duration::from_millis(50));
-            }
-        }));
+        // A switch that never reached create still applies to the next start
+        if let Some(xid) = state.pending_xid.take() {
+            let _ = state.xid.insert(xid);
+        }
 
         Ok(())
     }
 
-    fn stop(&self) -> Result<(), gst::ErrorMessage> {
-        if let Some(run) = self.state.lock().unwrap().resize_run.take() {
-            run.store(false, Ordering::SeqCst);
+    fn unlock(&self) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+        state.unlocked = true;
+        if let Some(id) = state.clock_wait.take() {
+            id.unschedule();
         }
-
-        if let Some(handle) = self.state.lock().unwrap().resize_handle.take() {
-            handle.join().unwrap();
+        if let Some(mut wake) = state.select_wake.take() {
+            let _ = wake.write_all(&[0]);
         }
 
-        self.state.lock().unwrap().connection.take();
+        Ok(())
+    }
 
+    fn unlock_stop(&self) -> Result<(), gst::ErrorMessage> {
+        self.state.lock().unwrap().unlocked = false;
         Ok(())
     }
 }
 
 impl ElementImpl for XImageRedux {
+    fn change_state(&self, transition: gst::StateChange) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        match transition {
+            gst::StateChange::PausedToPlaying | gst::StateChange::PausedToReady => self.stop_paused_capture(),
+            // Fails here rather than during negotiation when there's no X server to talk to
+            gst::StateChange::NullToReady => self.check_connection()?,
+            _ => {}
+        }
+
+        let res = self.parent_change_state(transition)?;
+
+        match transition {
+            gst::StateChange::PlayingToPaused if self.state.lock().unwrap().capture_when_paused => self.start_paused_capture(),
+            gst::StateChange::ReadyToNull => {
+                let mut state = self.state.lock().unwrap();
+                state.connection.take();
+                // Atoms are only valid for the connection's server
+                state.atoms.take();
+            }
+            _ => {}
+        }
+
+        Ok(res)
+    }
+
+    // Keeps connections handed to the element by the application or another element for the next connect
+    fn set_context(&self, context: &gst::Context) {
+        if context.context_type() == CONNECTION_CONTEXT_TYPE {
+            match context.structure().get::<SharedConnection>("connection") {
+                Ok(shared) => {
+                    debug!(CAT, "Got a connection context");
+                    let _ = self.state.lock().unwrap().context_connection.insert(shared);
+                }
+                Err(e) => warning!(CAT, "Ignoring invalid connection context, opening a connection instead: {}", e)
+            }
+        }
+
+        #[cfg(feature = "gl")]
+        self.state.lock().unwrap().gl.set_context(self.obj().upcast_ref(), context);
+
+        self.parent_set_context(context)
+    }
+
+    fn send_event(&self, event: gst::Event) -> bool {
+        // Intercept EOS so create can push the final frame before ending the stream itself
+        if let gst::EventView::Eos(_) = event.view() {
+            let mut state = self.state.lock().unwrap();
+            if state.repeat_last_frame_on_eos && state.last_frame.is_some() {
+                state.eos_requested = true;
+                return true;
+            }
+        }
+
+        self.parent_send_event(event)
+    }
+
     fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
         static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
             gst::subclass::ElementMetadata::new(
@@ -558,6 +4303,9 @@ impl ElementImpl for XImageRedux {
                     .build()
                 ).build();
 
+            #[cfg(feature = "gl")]
+            let caps = gl::with_memory_caps(caps);
+
             let src_pad_template = gst::PadTemplate::new(
                 "src",
                 gst::PadDirection::Src,
@@ -576,12 +4324,114 @@ impl ElementImpl for XImageRedux {
 impl ObjectImpl for XImageRedux {
     fn signals() -> &'static [glib::subclass::Signal] {
         static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
-            vec! [
+            #[allow(unused_mut)]
+            let mut signals = vec! [
                 glib::subclass::Signal::builder("resize")
                     // Width, height
                     .param_types([u32::static_type(), u32::static_type()])
+                    .build(),
+                glib::subclass::Signal::builder("region-changed")
+                    // Window-relative x, y, width, height of the region actually captured
+                    .param_types([i32::static_type(), i32::static_type(), u32::static_type(), u32::static_type()])
+                    .build(),
+                glib::subclass::Signal::builder("window-bound")
+                    // XID
+                    .param_types([u32::static_type()])
+                    .build(),
+                // Emitted before window-bound when the window came from the selection properties, wait-for-window or reattach
+                glib::subclass::Signal::builder("window-found")
+                    // XID, root-relative x, y, width, height
+                    .param_types([u32::static_type(), i32::static_type(), i32::static_type(), u32::static_type(), u32::static_type()])
+                    .build(),
+                glib::subclass::Signal::builder("warp-pointer")
+                    // Window-relative x, y
+                    .param_types([i32::static_type(), i32::static_type()])
+                    .return_type::<bool>()
+                    .action()
+                    .class_handler(|_, args| {
+                        let element = args[0].get::<super::XImageRedux>().unwrap();
+                        let x = args[1].get::<i32>().unwrap();
+                        let y = args[2].get::<i32>().unwrap();
+
+                        let res = match (i16::try_from(x), i16::try_from(y)) {
+                            (Ok(x), Ok(y)) => element.imp().warp_pointer(x, y),
+                            _ => Err(anyhow::anyhow!("Position {}x{} is out of range", x, y))
+                        };
+
+                        if let Err(e) = &res {
+                            error!(CAT, "Failed to warp pointer: {}", e);
+                        }
+
+                        Some(res.is_ok().to_value())
+                    })
+                    .build(),
+                glib::subclass::Signal::builder("scan-windows")
+                    // Array of window structures with xid, title, instance, class, geometry, visibility, and fullscreen
+                    .return_type::<gst::Array>()
+                    .action()
+                    .class_handler(|_, args| {
+                        let element = args[0].get::<super::XImageRedux>().unwrap();
+
+                        match element.imp().scan_windows() {
+                            Ok(windows) => Some(windows.to_value()),
+                            Err(e) => {
+                                error!(CAT, "Failed to scan windows: {}", e);
+                                Some(gst::Array::new(Vec::<glib::SendValue>::new()).to_value())
+                            }
+                        }
+                    })
+                    .build(),
+                glib::subclass::Signal::builder("thumbnail")
+                    // Maximum width or height, 0 for full size
+                    .param_types([u32::static_type()])
+                    .return_type::<gst::Sample>()
+                    .action()
+                    .class_handler(|_, args| {
+                        let element = args[0].get::<super::XImageRedux>().unwrap();
+                        let max_dimension = args[1].get::<u32>().unwrap();
+
+                        match element.imp().thumbnail(max_dimension) {
+                            Ok(sample) => Some(sample.to_value()),
+                            Err(e) => {
+                                error!(CAT, "Failed to create thumbnail: {}", e);
+                                Some(None::<gst::Sample>.to_value())
+                            }
+                        }
+                    })
+                    .build(),
+                glib::subclass::Signal::builder("snapshot")
+                    .return_type::<gst::Sample>()
+                    .action()
+                    .class_handler(|_, args| {
+                        let element = args[0].get::<super::XImageRedux>().unwrap();
+                        Some(element.imp().snapshot().to_value())
+                    })
                     .build()
-            ]
+            ];
+
+            // Injected buffers are BGRx and must be injected before starting to bypass X
+            #[cfg(feature = "test-inject")]
+            signals.push(glib::subclass::Signal::builder("inject-frame")
+                // Buffer, width, height
+                .param_types([gst::Buffer::static_type(), u32::static_type(), u32::static_type()])
+                .return_type::<bool>()
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::XImageRedux>().unwrap();
+                    let buffer = args[1].get::<gst::Buffer>().unwrap();
+                    let width = args[2].get::<u32>().unwrap();
+                    let height = args[3].get::<u32>().unwrap();
+
+                    let res = element.imp().inject_frame(buffer, width, height);
+                    if let Err(e) = &res {
+                        error!(CAT, "Failed to inject frame: {}", e);
+                    }
+
+                    Some(res.is_ok().to_value())
+                })
+                .build());
+
+            signals
         });
 
         SIGNALS.as_ref()
@@ -592,11 +4442,160 @@ impl ObjectImpl for XImageRedux {
             vec![
                 glib::ParamSpecUInt::builder("xid")
                     .nick("XID")
-                    .blurb("XID of window to capture")
+                    .blurb("XID of window to capture, 0 to find one with the window-* selection properties. Can be changed while playing to switch windows")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecString::builder("display")
+                    .nick("Display")
+                    .blurb("X display to capture from, e.g. \":1\" or \"otherhost:0\", instead of the DISPLAY environment variable")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("xauthority")
+                    .nick("Xauthority")
+                    .blurb("Xauthority file with the display's MIT-MAGIC-COOKIE-1, for displays owned by another user or session, instead of the XAUTHORITY environment variable")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecInt::builder("screen")
+                    .nick("Screen")
+                    .blurb("Screen number on multi-screen servers, e.g. 1 for :0.1, -1 for the display's default screen")
+                    .minimum(-1)
+                    .default_value(-1)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("capture-root")
+                    .nick("Capture Root")
+                    .blurb("When xid isn't set, capture the whole screen through its root window, like ximagesrc")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("monitor-name")
+                    .nick("Monitor Name")
+                    .blurb("With capture-root, only capture the RandR output with this name, e.g. HDMI-1. Takes precedence over monitor-index")
+                    .build(),
+                glib::ParamSpecInt::builder("monitor-index")
+                    .nick("Monitor Index")
+                    .blurb("With capture-root, only capture the nth active RandR output, -1 for all of them")
+                    .minimum(-1)
+                    .default_value(-1)
+                    .build(),
+                glib::ParamSpecBoolean::builder("reattach")
+                    .nick("Reattach")
+                    .blurb("When an explicitly set xid is destroyed, continue with the next window that has the same WM_CLASS and _NET_WM_PID, for apps that recreate their toplevel")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("wait-for-window")
+                    .nick("Wait For Window")
+                    .blurb("If the window doesn't exist when starting, push blank gap frames until it appears instead of failing")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder::<ToplevelMode>("toplevel-mode")
+                    .nick("Toplevel Mode")
+                    .blurb("Window captured when xid is a child window, walking up the tree with XQueryTree")
+                    .default_value(ToplevelMode::Client)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder::<WindowType>("window-type")
+                    .nick("Window Type")
+                    .blurb("Capture the first window with this _NET_WM_WINDOW_TYPE when xid isn't set")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("window-name")
+                    .nick("Window Name")
+                    .blurb("Capture the first window whose title or WM_CLASS matches this when xid isn't set, see window-name-match")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder::<NameMatch>("window-name-match")
+                    .nick("Window Name Match")
+                    .blurb("How window-name is compared, substring matching also ignores case")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("window-class")
+                    .nick("Window Class")
+                    .blurb("Capture the first window whose WM_CLASS instance or class is this when xid isn't set, or \"instance,class\" to match both")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("select-on-start")
+                    .nick("Select On Start")
+                    .blurb("When xid isn't set, grab the pointer on start and capture the window that's clicked, like xwininfo. Start fails if nothing is clicked within 30 seconds")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("follow-active-window")
+                    .nick("Follow Active Window")
+                    .blurb("Capture whichever window has focus according to _NET_ACTIVE_WINDOW, retargeting and renegotiating when it changes")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("window-pid")
+                    .nick("Window PID")
+                    .blurb("Capture the first window whose _NET_WM_PID is this when xid isn't set (0 for any)")
+                    .mutable_ready()
                     .build(),
                 glib::ParamSpecBoolean::builder("show-cursor")
                     .nick("Show Cursor")
                     .blurb("Whether or not to show the cursor (requires XFixes)")
+                    .default_value(DEFAULT_SHOW_CURSOR)
+                    .build(),
+                glib::ParamSpecEnum::builder::<CursorMode>("cursor-mode")
+                    .nick("Cursor Mode")
+                    .blurb("When to draw the cursor while show-cursor is on, e.g. only while the captured window is focused for picture-in-picture")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder::<CursorFallback>("cursor-fallback")
+                    .nick("Cursor Fallback")
+                    .blurb("How to draw the cursor when XFixes is unavailable (synthetic won't match the real cursor shape)")
+                    .build(),
+                glib::ParamSpecString::builder("cursor-image-location")
+                    .nick("Cursor Image Location")
+                    .blurb("PNG drawn in place of the real cursor, e.g. a large high-contrast pointer")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoxed::builder::<gst::Sample>("cursor-image")
+                    .nick("Cursor Image")
+                    .blurb("Raw video sample (e.g. ARGB) drawn in place of the real cursor, takes priority over cursor-image-location")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("cursor-hotspot-x")
+                    .nick("Cursor Hotspot X")
+                    .blurb("Column of the custom cursor image placed at the pointer position")
+                    .maximum(u16::MAX.into())
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("cursor-hotspot-y")
+                    .nick("Cursor Hotspot Y")
+                    .blurb("Row of the custom cursor image placed at the pointer position")
+                    .maximum(u16::MAX.into())
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecDouble::builder("cursor-scale")
+                    .nick("Cursor Scale")
+                    .blurb("Scale applied to the cursor, 0 to follow the screen's DPI (Xft.dpi / 96) so it isn't tiny in HiDPI captures")
+                    .minimum(0.0)
+                    .maximum(16.0)
+                    .default_value(1.0)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("cursor-overlay")
+                    .nick("Cursor Overlay")
+                    .blurb("Attach the cursor as GstVideoOverlayCompositionMeta instead of drawing it into frames, downstream renders or drops it")
+                    .build(),
+                glib::ParamSpecBoolean::builder("highlight-clicks")
+                    .nick("Highlight Clicks")
+                    .blurb("Draw a fading ring around the pointer when a button is pressed (requires XInput2)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("pointer-meta")
+                    .nick("Pointer Meta")
+                    .blurb("Attach a \"pointer\" GstVideoRegionOfInterestMeta with an ximageredux/pointer param (x, y, buttons) to buffers while the pointer is in the capture region")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("capture-cursor-only-when-moving")
+                    .nick("Capture Cursor Only When Moving")
+                    .blurb("Only fetch the cursor image again when the pointer moves, reusing the last one otherwise")
+                    .build(),
+                glib::ParamSpecInt::builder("pointer-device")
+                    .nick("Pointer Device")
+                    .blurb("XInput2 device ID of the pointer to track for the cursor, for multi-seat setups (-1 for the core pointer)")
+                    .minimum(-1)
+                    .maximum(u16::MAX as i32)
+                    .default_value(-1)
                     .build(),
                 glib::ParamSpecUInt::builder("width")
                     .nick("Width")
@@ -609,6 +4608,177 @@ impl ObjectImpl for XImageRedux {
                 glib::ParamSpecEnum::builder::<WindowVisibility>("visibility")
                     .nick("Visibility")
                     .blurb("The current window's visiblity")
+                    .build(),
+                glib::ParamSpecBoolean::builder("fullscreen")
+                    .nick("Fullscreen")
+                    .blurb("Whether the window is currently fullscreen")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecInt::builder("crop-x")
+                    .nick("Crop X")
+                    .blurb("Left edge of the captured region, see crop-coordinates")
+                    .build(),
+                glib::ParamSpecInt::builder("crop-y")
+                    .nick("Crop Y")
+                    .blurb("Top edge of the captured region, see crop-coordinates")
+                    .build(),
+                glib::ParamSpecUInt::builder("crop-width")
+                    .nick("Crop Width")
+                    .blurb("Width of the captured region (0 extends to the window edge)")
+                    .build(),
+                glib::ParamSpecUInt::builder("crop-height")
+                    .nick("Crop Height")
+                    .blurb("Height of the captured region (0 extends to the window edge)")
+                    .build(),
+                glib::ParamSpecEnum::builder::<CropCoordinates>("crop-coordinates")
+                    .nick("Crop Coordinates")
+                    .blurb("Whether crop-x/crop-y are relative to the window or the screen root")
+                    .build(),
+                glib::ParamSpecBoolean::builder("crop-decorations")
+                    .nick("Crop Decorations")
+                    .blurb("When xid is a window manager frame, crop the borders in _NET_FRAME_EXTENTS to capture only the client area")
+                    .build(),
+                glib::ParamSpecBoolean::builder("crop-csd-shadows")
+                    .nick("Crop CSD Shadows")
+                    .blurb("Crop the transparent shadow margins client-side decorated windows advertise in _GTK_FRAME_EXTENTS")
+                    .default_value(true)
+                    .build(),
+                glib::ParamSpecBoolean::builder("redirect-subwindows")
+                    .nick("Redirect Subwindows")
+                    .blurb("Use Composite to include up-to-date child window content (e.g. embedded video), at extra server cost")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("offscreen")
+                    .nick("Offscreen Capture")
+                    .blurb("Capture from the window's Composite pixmap so covered or minimized windows show their own content, captures what's on screen without Composite")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder::<BackingStore>("backing-store")
+                    .nick("Backing Store")
+                    .blurb("Ask the server to retain obscured window contents, a lighter alternative to redirect-subwindows that not all servers honor")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("repeat-last-frame-on-eos")
+                    .nick("Repeat Last Frame On EOS")
+                    .blurb("Push a final copy of the last frame before EOS so encoders can flush cleanly")
+                    .build(),
+                glib::ParamSpecBoolean::builder("flush-on-resize")
+                    .nick("Flush On Resize")
+                    .blurb("Drop the last frame when the captured size changes, so a failed capture can't repeat a frame that no longer matches the caps")
+                    .default_value(true)
+                    .build(),
+                glib::ParamSpecBoolean::builder("stop-on-hidden")
+                    .nick("Stop On Hidden")
+                    .blurb("End the stream with EOS when the window becomes hidden (e.g. minimized)")
+                    .build(),
+                glib::ParamSpecBoolean::builder("still-image")
+                    .nick("Still Image")
+                    .blurb("Capture a single frame with 0/1 framerate for image encoders, then send EOS")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("capture-when-paused")
+                    .nick("Capture When Paused")
+                    .blurb("Keep refreshing the last frame (see the snapshot signal) while PAUSED")
+                    .build(),
+                glib::ParamSpecEnum::builder::<CaptureBackend>("capture-backend")
+                    .nick("Capture Backend")
+                    .blurb("How to time captures, present waits for complete frames to avoid tearing (falls back to get-image without Present)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("use-shm")
+                    .nick("Use SHM")
+                    .blurb("Capture through MIT-SHM shared memory when the server supports it, disable for remote displays")
+                    .default_value(true)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("use-damage")
+                    .nick("Use Damage")
+                    .blurb("Use XDamage to skip grabbing frames when the window content hasn't changed")
+                    .default_value(true)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("damage-regions")
+                    .nick("Damage Regions")
+                    .blurb("With use-damage, grab only the changed rectangles and patch them into the previous frame")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("damage-meta")
+                    .nick("Damage Meta")
+                    .blurb("Attach a \"damage\" GstVideoRegionOfInterestMeta for every rectangle that changed since the previous frame, for encoders that only encode dirty regions")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder::<TimestampMode>("timestamp-mode")
+                    .nick("Timestamp Mode")
+                    .blurb("Clock used for buffer timestamps, anything but running-time won't sync against the pipeline clock")
+                    .build(),
+                glib::ParamSpecBoolean::builder("measure-latency")
+                    .nick("Measure Latency")
+                    .blurb("Log the time spent in the X round-trip and buffer copy for each frame at debug level")
+                    .build(),
+                glib::ParamSpecBoolean::builder("log-x-errors")
+                    .nick("Log X Errors")
+                    .blurb("Log asynchronous X errors with their error code and request at warning level, for bug reports")
+                    .build(),
+                glib::ParamSpecBoolean::builder("benchmark")
+                    .nick("Benchmark")
+                    .blurb("Every second, log and update stats with the achieved framerate, average X round-trip, and fraction of repeated frames")
+                    .build(),
+                glib::ParamSpecBoxed::builder::<gst::Structure>("stats")
+                    .nick("Statistics")
+                    .blurb("Results of the last benchmark second and the number of times the window watcher was restarted")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecDouble::builder("scale-factor")
+                    .nick("Scale Factor")
+                    .blurb("Framebuffer pixels per physical pixel of the output the window is on, from RandR")
+                    .default_value(1.0)
+                    .read_only()
+                    .build(),
+                glib::ParamSpecString::builder("title")
+                    .nick("Title")
+                    .blurb("The current window title, invalid characters are replaced")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecBoxed::builder::<glib::Bytes>("title-raw")
+                    .nick("Raw Title")
+                    .blurb("The current window title's bytes as set by the window, in its original encoding")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecBoolean::builder("last-frame-was-fresh")
+                    .nick("Last Frame Was Fresh")
+                    .blurb("Whether the last pushed frame was newly captured rather than a repeat of the previous one")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt::builder("frame-repeat-limit")
+                    .nick("Frame Repeat Limit")
+                    .blurb("Post an error after this many consecutive failed captures reused the last frame (0 for no limit)")
+                    .build(),
+                gst::ParamSpecFraction::builder("pixel-aspect-ratio")
+                    .nick("Pixel Aspect Ratio")
+                    .blurb("Pixel aspect ratio to advertise in the caps")
+                    .minimum(gst::Fraction::new(1, i32::MAX))
+                    .maximum(gst::Fraction::new(i32::MAX, 1))
+                    .default_value(gst::Fraction::new(1, 1))
+                    .build(),
+                glib::ParamSpecUInt::builder("align")
+                    .nick("Align")
+                    .blurb("Align each row to this many bytes and attach a GstVideoMeta with the stride, for hardware encoders (0 to disable)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder::<OutputFormat>("output-format")
+                    .nick("Output Format")
+                    .blurb("Convert frames to YUV on the CPU and advertise it directly, so encoders don't need a videoconvert")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder::<Colorimetry>("colorimetry")
+                    .nick("Colorimetry")
+                    .blurb("Matrix used for YUV output-formats, also advertised in the caps")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("min-frame-interval-ms")
+                    .nick("Minimum Frame Interval")
+                    .blurb("Minimum time between captured frames in milliseconds, regardless of framerate (0 to disable)")
+                    .default_value(DEFAULT_MIN_FRAME_INTERVAL_MS)
                     .build()
             ]
         });
@@ -617,9 +4787,130 @@ impl ObjectImpl for XImageRedux {
     }
 
     fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        // These are only read when starting, changing them later would leave capture and watcher disagreeing
+        if pspec.flags().contains(gst::PARAM_FLAG_MUTABLE_READY) && self.obj().current_state() > gst::State::Ready {
+            warning!(CAT, "Ignoring change to {} outside of NULL/READY", pspec.name());
+            return;
+        }
+
         match pspec.name() {
-            "xid" => self.state.lock().unwrap().xid = Some(value.get::<Xid>().unwrap()),
+            "xid" if self.obj().current_state() > gst::State::Ready => {
+                let xid = value.get::<Xid>().unwrap();
+                if xid == 0 {
+                    warning!(CAT, "Ignoring xid 0 while streaming, windows are only resolved when starting");
+                    return;
+                }
+
+                // Applied by the streaming thread so capture never sees a half-switched window
+                let _ = self.state.lock().unwrap().pending_xid.insert(xid);
+            }
+            "xid" => {
+                let mut state = self.state.lock().unwrap();
+                // 0 unsets the xid so the window is resolved from the selection properties again
+                state.xid = Some(value.get::<Xid>().unwrap()).filter(|xid| *xid != 0);
+                state.xid_resolved = false;
+            }
+            "display" | "xauthority" => {
+                let mut state = self.state.lock().unwrap();
+                let value = value.get::<Option<String>>().unwrap().filter(|value| !value.is_empty());
+
+                let changed = match pspec.name() {
+                    "display" => std::mem::replace(&mut state.display, value.clone()) != value,
+                    _ => std::mem::replace(&mut state.xauthority, value.map(PathBuf::from)) != state.xauthority
+                };
+
+                // The connection is opened at NULL->READY, connecting differently needs a new one
+                if changed && state.connection.is_some() {
+                    state.connection.take();
+                    state.atoms.take();
+                }
+            }
+            "screen" => {
+                let mut state = self.state.lock().unwrap();
+                state.screen = value.get::<i32>().unwrap();
+
+                // The connection is already open in READY, so the screen is switched on it directly
+                let switch = state.connection.as_ref().is_some_and(|conn| screen_exists(conn, state.screen));
+                if switch {
+                    state.screen_num = Some(state.screen);
+                }
+            }
+            "capture-root" => self.state.lock().unwrap().capture_root = value.get::<bool>().unwrap(),
+            "monitor-name" | "monitor-index" => {
+                let mut state = self.state.lock().unwrap();
+                match pspec.name() {
+                    "monitor-name" => state.monitor_name = value.get::<Option<String>>().unwrap().filter(|name| !name.is_empty()),
+                    _ => state.monitor_index = value.get::<i32>().unwrap()
+                }
+
+                // Recomputes the region and renegotiates on the next frame
+                state.needs_size_update = true;
+            }
+            "reattach" => self.state.lock().unwrap().reattach = value.get::<bool>().unwrap(),
+            "wait-for-window" => self.state.lock().unwrap().wait_for_window = value.get::<bool>().unwrap(),
+            "toplevel-mode" => self.state.lock().unwrap().toplevel_mode = value.get::<ToplevelMode>().unwrap(),
+            "window-type" => self.state.lock().unwrap().window_type = value.get::<WindowType>().unwrap(),
+            "window-name" => self.state.lock().unwrap().window_name = value.get::<Option<String>>().unwrap().filter(|name| !name.is_empty()),
+            "window-name-match" => self.state.lock().unwrap().name_match = value.get::<NameMatch>().unwrap(),
+            "window-class" => self.state.lock().unwrap().window_class = value.get::<Option<String>>().unwrap().filter(|class| !class.is_empty()),
+            "window-pid" => self.state.lock().unwrap().window_pid = value.get::<u32>().unwrap(),
+            "select-on-start" => self.state.lock().unwrap().select_on_start = value.get::<bool>().unwrap(),
+            "follow-active-window" => self.state.lock().unwrap().follow_active_window = value.get::<bool>().unwrap(),
             "show-cursor" => self.state.lock().unwrap().show_cursor = value.get::<bool>().unwrap(),
+            "cursor-fallback" => self.state.lock().unwrap().cursor_fallback = value.get::<CursorFallback>().unwrap(),
+            "cursor-mode" => self.state.lock().unwrap().cursor_mode = value.get::<CursorMode>().unwrap(),
+            "cursor-image-location" => self.state.lock().unwrap().cursor_image_location = value.get::<Option<String>>().unwrap().filter(|value| !value.is_empty()).map(PathBuf::from),
+            "cursor-image" => self.state.lock().unwrap().cursor_image = value.get::<Option<gst::Sample>>().unwrap(),
+            "cursor-hotspot-x" => self.state.lock().unwrap().cursor_hotspot_x = value.get::<u32>().unwrap(),
+            "cursor-hotspot-y" => self.state.lock().unwrap().cursor_hotspot_y = value.get::<u32>().unwrap(),
+            "cursor-scale" => self.state.lock().unwrap().cursor_scale = value.get::<f64>().unwrap(),
+            "cursor-overlay" => self.state.lock().unwrap().cursor_overlay = value.get::<bool>().unwrap(),
+            "highlight-clicks" => self.state.lock().unwrap().highlight_clicks = value.get::<bool>().unwrap(),
+            "pointer-meta" => self.state.lock().unwrap().pointer_meta = value.get::<bool>().unwrap(),
+            "capture-cursor-only-when-moving" => self.state.lock().unwrap().cursor_only_when_moving = value.get::<bool>().unwrap(),
+            "pointer-device" => self.state.lock().unwrap().pointer_device = value.get::<i32>().unwrap(),
+            "crop-x" | "crop-y" | "crop-width" | "crop-height" | "crop-coordinates" | "crop-decorations" | "crop-csd-shadows" => {
+                let mut state = self.state.lock().unwrap();
+                match pspec.name() {
+                    "crop-x" => state.crop_x = value.get::<i32>().unwrap(),
+                    "crop-y" => state.crop_y = value.get::<i32>().unwrap(),
+                    "crop-width" => state.crop_width = value.get::<u32>().unwrap(),
+                    "crop-height" => state.crop_height = value.get::<u32>().unwrap(),
+                    "crop-decorations" => state.crop_decorations = value.get::<bool>().unwrap(),
+                    "crop-csd-shadows" => state.crop_csd_shadows = value.get::<bool>().unwrap(),
+                    _ => state.crop_coordinates = value.get::<CropCoordinates>().unwrap()
+                }
+
+                // Recomputes the region and renegotiates on the next frame
+                state.needs_size_update = true;
+            }
+            "redirect-subwindows" => self.state.lock().unwrap().redirect_subwindows = value.get::<bool>().unwrap(),
+            "offscreen" => self.state.lock().unwrap().offscreen = value.get::<bool>().unwrap(),
+            "backing-store" => self.state.lock().unwrap().backing_store = value.get::<BackingStore>().unwrap(),
+            "repeat-last-frame-on-eos" => self.state.lock().unwrap().repeat_last_frame_on_eos = value.get::<bool>().unwrap(),
+            "flush-on-resize" => self.state.lock().unwrap().flush_on_resize = value.get::<bool>().unwrap(),
+            "stop-on-hidden" => self.state.lock().unwrap().stop_on_hidden = value.get::<bool>().unwrap(),
+            "still-image" => self.state.lock().unwrap().still_image = value.get::<bool>().unwrap(),
+            "capture-when-paused" => self.state.lock().unwrap().capture_when_paused = value.get::<bool>().unwrap(),
+            "capture-backend" => self.state.lock().unwrap().capture_backend = value.get::<CaptureBackend>().unwrap(),
+            "use-shm" => self.state.lock().unwrap().use_shm = value.get::<bool>().unwrap(),
+            "use-damage" => self.state.lock().unwrap().use_damage = value.get::<bool>().unwrap(),
+            "damage-regions" => self.state.lock().unwrap().damage_regions = value.get::<bool>().unwrap(),
+            "damage-meta" => self.state.lock().unwrap().damage_meta = value.get::<bool>().unwrap(),
+            "timestamp-mode" => self.state.lock().unwrap().timestamp_mode = value.get::<TimestampMode>().unwrap(),
+            "measure-latency" => self.state.lock().unwrap().measure_latency = value.get::<bool>().unwrap(),
+            "log-x-errors" => self.state.lock().unwrap().log_x_errors = value.get::<bool>().unwrap(),
+            "benchmark" => {
+                let mut state = self.state.lock().unwrap();
+                state.benchmark = value.get::<bool>().unwrap();
+                state.benchmark_window = BenchmarkWindow::default();
+            }
+            "frame-repeat-limit" => self.state.lock().unwrap().frame_repeat_limit = value.get::<u32>().unwrap(),
+            "pixel-aspect-ratio" => self.state.lock().unwrap().pixel_aspect_ratio = value.get::<gst::Fraction>().unwrap(),
+            "align" => self.state.lock().unwrap().align = value.get::<u32>().unwrap(),
+            "output-format" => self.state.lock().unwrap().output_format = value.get::<OutputFormat>().unwrap(),
+            "colorimetry" => self.state.lock().unwrap().colorimetry = value.get::<Colorimetry>().unwrap(),
+            "min-frame-interval-ms" => self.state.lock().unwrap().min_frame_interval = Duration::from_millis(value.get::<u32>().unwrap() as u64),
             // Doesn't do anything on purpose, just dummy so impls can read values
             "visibility" | "width" | "height" => {},
             _ => unimplemented!()
@@ -628,11 +4919,78 @@ impl ObjectImpl for XImageRedux {
 
     fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
         match pspec.name() {
-            "xid" => self.state.lock().unwrap().xid.unwrap_or(0).to_value(),
+            "xid" => {
+                let state = self.state.lock().unwrap();
+                state.pending_xid.or(state.xid).unwrap_or(0).to_value()
+            }
+            "display" => self.state.lock().unwrap().display.to_value(),
+            "xauthority" => self.state.lock().unwrap().xauthority.as_ref().map(|path| path.to_string_lossy().into_owned()).to_value(),
+            "screen" => self.state.lock().unwrap().screen.to_value(),
+            "capture-root" => self.state.lock().unwrap().capture_root.to_value(),
+            "monitor-name" => self.state.lock().unwrap().monitor_name.to_value(),
+            "monitor-index" => self.state.lock().unwrap().monitor_index.to_value(),
+            "reattach" => self.state.lock().unwrap().reattach.to_value(),
+            "wait-for-window" => self.state.lock().unwrap().wait_for_window.to_value(),
+            "toplevel-mode" => self.state.lock().unwrap().toplevel_mode.to_value(),
+            "window-type" => self.state.lock().unwrap().window_type.to_value(),
+            "window-name" => self.state.lock().unwrap().window_name.to_value(),
+            "window-name-match" => self.state.lock().unwrap().name_match.to_value(),
+            "window-class" => self.state.lock().unwrap().window_class.to_value(),
+            "window-pid" => self.state.lock().unwrap().window_pid.to_value(),
+            "select-on-start" => self.state.lock().unwrap().select_on_start.to_value(),
+            "follow-active-window" => self.state.lock().unwrap().follow_active_window.to_value(),
             "show-cursor" => self.state.lock().unwrap().show_cursor.to_value(),
-            "width" => (self.state.lock().unwrap().size.unwrap_or(Size::default()).width as u32).to_value(),
-            "height" => (self.state.lock().unwrap().size.unwrap_or(Size::default()).height as u32).to_value(),
+            "cursor-fallback" => self.state.lock().unwrap().cursor_fallback.to_value(),
+            "cursor-mode" => self.state.lock().unwrap().cursor_mode.to_value(),
+            "cursor-image-location" => self.state.lock().unwrap().cursor_image_location.as_ref().map(|path| path.to_string_lossy().into_owned()).to_value(),
+            "cursor-image" => self.state.lock().unwrap().cursor_image.to_value(),
+            "cursor-hotspot-x" => self.state.lock().unwrap().cursor_hotspot_x.to_value(),
+            "cursor-hotspot-y" => self.state.lock().unwrap().cursor_hotspot_y.to_value(),
+            "cursor-scale" => self.state.lock().unwrap().cursor_scale.to_value(),
+            "cursor-overlay" => self.state.lock().unwrap().cursor_overlay.to_value(),
+            "highlight-clicks" => self.state.lock().unwrap().highlight_clicks.to_value(),
+            "pointer-meta" => self.state.lock().unwrap().pointer_meta.to_value(),
+            "capture-cursor-only-when-moving" => self.state.lock().unwrap().cursor_only_when_moving.to_value(),
+            "pointer-device" => self.state.lock().unwrap().pointer_device.to_value(),
+            "width" => (self.state.lock().unwrap().size.unwrap_or_default().width as u32).to_value(),
+            "height" => (self.state.lock().unwrap().size.unwrap_or_default().height as u32).to_value(),
             "visibility" => self.state.lock().unwrap().visibility.to_value(),
+            "fullscreen" => self.state.lock().unwrap().fullscreen.to_value(),
+            "title" => self.state.lock().unwrap().title.to_value(),
+            "title-raw" => self.state.lock().unwrap().title_raw.as_deref().map(glib::Bytes::from).to_value(),
+            "scale-factor" => self.state.lock().unwrap().scale_factor.to_value(),
+            "crop-x" => self.state.lock().unwrap().crop_x.to_value(),
+            "crop-y" => self.state.lock().unwrap().crop_y.to_value(),
+            "crop-width" => self.state.lock().unwrap().crop_width.to_value(),
+            "crop-height" => self.state.lock().unwrap().crop_height.to_value(),
+            "crop-coordinates" => self.state.lock().unwrap().crop_coordinates.to_value(),
+            "crop-decorations" => self.state.lock().unwrap().crop_decorations.to_value(),
+            "crop-csd-shadows" => self.state.lock().unwrap().crop_csd_shadows.to_value(),
+            "redirect-subwindows" => self.state.lock().unwrap().redirect_subwindows.to_value(),
+            "offscreen" => self.state.lock().unwrap().offscreen.to_value(),
+            "backing-store" => self.state.lock().unwrap().backing_store.to_value(),
+            "repeat-last-frame-on-eos" => self.state.lock().unwrap().repeat_last_frame_on_eos.to_value(),
+            "flush-on-resize" => self.state.lock().unwrap().flush_on_resize.to_value(),
+            "stop-on-hidden" => self.state.lock().unwrap().stop_on_hidden.to_value(),
+            "still-image" => self.state.lock().unwrap().still_image.to_value(),
+            "capture-when-paused" => self.state.lock().unwrap().capture_when_paused.to_value(),
+            "capture-backend" => self.state.lock().unwrap().capture_backend.to_value(),
+            "use-shm" => self.state.lock().unwrap().use_shm.to_value(),
+            "use-damage" => self.state.lock().unwrap().use_damage.to_value(),
+            "damage-regions" => self.state.lock().unwrap().damage_regions.to_value(),
+            "damage-meta" => self.state.lock().unwrap().damage_meta.to_value(),
+            "timestamp-mode" => self.state.lock().unwrap().timestamp_mode.to_value(),
+            "measure-latency" => self.state.lock().unwrap().measure_latency.to_value(),
+            "log-x-errors" => self.state.lock().unwrap().log_x_errors.to_value(),
+            "benchmark" => self.state.lock().unwrap().benchmark.to_value(),
+            "stats" => self.state.lock().unwrap().stats.to_value(),
+            "last-frame-was-fresh" => self.state.lock().unwrap().last_frame_was_fresh.to_value(),
+            "frame-repeat-limit" => self.state.lock().unwrap().frame_repeat_limit.to_value(),
+            "pixel-aspect-ratio" => self.state.lock().unwrap().pixel_aspect_ratio.to_value(),
+            "align" => self.state.lock().unwrap().align.to_value(),
+            "output-format" => self.state.lock().unwrap().output_format.to_value(),
+            "colorimetry" => self.state.lock().unwrap().colorimetry.to_value(),
+            "min-frame-interval-ms" => (self.state.lock().unwrap().min_frame_interval.as_millis() as u32).to_value(),
             _ => unimplemented!()
         }
     }
@@ -644,4 +5002,45 @@ impl ObjectImpl for XImageRedux {
     }
 }
 
-impl GstObjectImpl for XImageRedux {}
\ No newline at end of file
+impl GstObjectImpl for XImageRedux {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A window's depth can't change in X, so the comparison is tested on the state directly
+    #[test]
+    fn depth_change_drops_frames_of_the_old_depth() {
+        gst::init().unwrap();
+
+        let mut state = State::default();
+        let _ = state.depth.insert(24);
+        let _ = state.last_capture.insert(gst::Buffer::new());
+        let _ = state.last_frame.insert(gst::Buffer::new());
+        state.damage_full = false;
+
+        assert!(!forget_changed_depth(&mut state, 24));
+        assert_eq!(state.depth, Some(24));
+        assert!(state.last_capture.is_some() && state.last_frame.is_some());
+
+        assert!(forget_changed_depth(&mut state, 32));
+        assert_eq!(state.depth, None);
+        assert!(state.damage_full);
+        assert!(state.last_capture.is_none() && state.last_frame.is_none());
+
+        // Nothing cached yet is the first format lookup, not a change
+        assert!(!forget_changed_depth(&mut state, 32));
+    }
+
+    #[test]
+    fn depth_change_keeps_last_frame_without_flush_on_resize() {
+        gst::init().unwrap();
+
+        let mut state = State { flush_on_resize: false, ..State::default() };
+        let _ = state.depth.insert(24);
+        let _ = state.last_frame.insert(gst::Buffer::new());
+
+        assert!(forget_changed_depth(&mut state, 32));
+        assert!(state.last_frame.is_some());
+    }
+}
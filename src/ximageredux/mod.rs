@@ -1,6 +1,19 @@
 use gst::{glib, prelude::{StaticType, PluginApiExt}};
 
+mod cursor;
+#[cfg(feature = "gl")]
+mod gl;
 mod imp;
+mod monitor;
+#[cfg(feature = "image")]
+mod rgba;
+mod shm;
+mod thumbnail;
+pub(crate) mod window;
+mod xauth;
+mod yuv;
+
+pub use window::{WindowInfo, list_windows};
 
 glib::wrapper! {
     pub struct XImageRedux(ObjectSubclass<imp::XImageRedux>) @extends gst_base::PushSrc, gst_base::BaseSrc, gst::Element, gst::Object;
@@ -12,6 +25,21 @@ impl Default for XImageRedux {
     }
 }
 
+#[cfg(feature = "image")]
+impl XImageRedux {
+    /// Latest captured frame as RGBA, with the channels reordered from whatever format X provided.
+    ///
+    /// Formats without alpha get an opaque alpha channel. Returns `None` before the first frame or if the
+    /// negotiated format isn't packed 8-bit RGB.
+    pub fn latest_rgba_image(&self) -> Option<image::RgbaImage> {
+        use gst::subclass::prelude::ObjectSubclassIsExt;
+
+        let sample = self.imp().snapshot()?;
+        let info = gst_video::VideoInfo::from_caps(sample.caps()?).ok()?;
+        rgba::to_rgba_image(sample.buffer()?, &info)
+    }
+}
+
 unsafe impl Send for XImageRedux {}
 unsafe impl Sync for XImageRedux {}
 
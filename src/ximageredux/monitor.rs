@@ -0,0 +1,69 @@
+use anyhow::Result;
+use xcb::{x, randr, Connection, Xid as _};
+
+// A lit RandR output and the part of the root it shows
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    pub name: String,
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16
+}
+
+// Outputs driven by a CRTC, in the server's output order, which is what monitor-index counts in
+pub fn list(conn: &Connection, root: x::Window) -> Result<Vec<Monitor>> {
+    let resources = conn.wait_for_reply(conn.send_request(&randr::GetScreenResourcesCurrent { window: root }))?;
+
+    let cookies: Vec<_> = resources.outputs().iter().map(|output| conn.send_request(&randr::GetOutputInfo {
+        output: *output,
+        config_timestamp: resources.config_timestamp()
+    })).collect();
+
+    let mut monitors = Vec::new();
+    for cookie in cookies {
+        let output = conn.wait_for_reply(cookie)?;
+        if output.crtc().is_none() {
+            continue;
+        }
+
+        let crtc = conn.wait_for_reply(conn.send_request(&randr::GetCrtcInfo {
+            crtc: output.crtc(),
+            config_timestamp: resources.config_timestamp()
+        }))?;
+
+        if crtc.mode().is_none() {
+            continue;
+        }
+
+        monitors.push(Monitor {
+            name: String::from_utf8_lossy(output.name()).into_owned(),
+            x: crtc.x(),
+            y: crtc.y(),
+            width: crtc.width(),
+            height: crtc.height()
+        });
+    }
+
+    Ok(monitors)
+}
+
+// Xft.dpi from RESOURCE_MANAGER, which is what toolkits scale their UI to, else the screen's physical DPI
+pub fn dpi(conn: &Connection, screen: &x::Screen) -> Option<f64> {
+    // Resources live on the first screen's root regardless of which screen is captured
+    let resources = conn.get_setup().roots().next().and_then(|first| conn.wait_for_reply(conn.send_request(&x::GetProperty {
+        delete: false,
+        window: first.root(),
+        property: x::ATOM_RESOURCE_MANAGER,
+        r#type: x::ATOM_STRING,
+        long_offset: 0,
+        long_length: u32::MAX
+    })).ok());
+
+    let xft_dpi = resources.and_then(|reply| String::from_utf8_lossy(reply.value::<u8>())
+        .lines()
+        .find_map(|line| line.strip_prefix("Xft.dpi:")?.trim().parse::<f64>().ok()));
+
+    xft_dpi.filter(|dpi| *dpi > 0.0).or_else(|| (screen.width_in_millimeters() > 0)
+        .then(|| f64::from(screen.width_in_pixels()) * 25.4 / f64::from(screen.width_in_millimeters())))
+}
@@ -0,0 +1,27 @@
+use gst_video::{VideoFrameRef, VideoInfo};
+use image::RgbaImage;
+
+use super::cursor;
+
+// Converts a packed 8-bit RGB frame using its stride from the VideoMeta, so aligned rows are handled
+// Alpha is copied as is, X leaves it premultiplied for ARGB visuals
+pub fn to_rgba_image(buffer: &gst::BufferRef, info: &VideoInfo) -> Option<RgbaImage> {
+    let format_info = info.format_info();
+    let (pixel_stride, [r, g, b]) = cursor::rgb_offsets(&format_info)?;
+    let a = format_info.has_alpha().then(|| format_info.poffset()[3] as usize);
+
+    let frame = VideoFrameRef::from_buffer_ref_readable(buffer, info).ok()?;
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data(0).ok()?;
+
+    let mut image = RgbaImage::new(info.width(), info.height());
+    for (y, row) in image.rows_mut().enumerate() {
+        let src = &data[y * stride..];
+        for (x, pixel) in row.enumerate() {
+            let src = &src[x * pixel_stride..(x + 1) * pixel_stride];
+            pixel.0 = [src[r], src[g], src[b], a.map(|a| src[a]).unwrap_or(255)];
+        }
+    }
+
+    Some(image)
+}
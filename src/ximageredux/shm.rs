@@ -0,0 +1,69 @@
+use anyhow::{Result, bail};
+use xcb::{shm, Connection};
+
+// SysV shared memory segment attached to both this process and the X server
+pub struct Segment {
+    seg: shm::Seg,
+    addr: *mut libc::c_void,
+    size: usize
+}
+
+// The mapping is only read while the state mutex is held
+unsafe impl Send for Segment {}
+
+impl Segment {
+    pub fn new(conn: &Connection, size: usize) -> Result<Self> {
+        let id = unsafe { libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600) };
+        if id < 0 {
+            bail!("shmget failed: {}", std::io::Error::last_os_error());
+        }
+
+        let addr = unsafe { libc::shmat(id, std::ptr::null(), libc::SHM_RDONLY) };
+        if addr as isize == -1 {
+            let e = std::io::Error::last_os_error();
+            unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+            bail!("shmat failed: {}", e);
+        }
+
+        let seg: shm::Seg = conn.generate_id();
+        let res = conn.send_and_check_request(&shm::Attach {
+            shmseg: seg,
+            shmid: id as u32,
+            read_only: false
+        });
+
+        // Marked for removal right away, it's freed once both sides detach, even if this process crashes
+        unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+
+        if let Err(e) = res {
+            unsafe { libc::shmdt(addr) };
+            bail!("The server couldn't attach the segment, it's likely remote: {}", e);
+        }
+
+        Ok(Self { seg, addr, size })
+    }
+
+    pub fn seg(&self) -> shm::Seg {
+        self.seg
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn data(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.size) }
+    }
+
+    // Detaches from the server too, dropping only detaches locally
+    pub fn release(self, conn: &Connection) -> Result<()> {
+        conn.send_and_check_request(&shm::Detach { shmseg: self.seg })?;
+        Ok(())
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        unsafe { libc::shmdt(self.addr) };
+    }
+}
@@ -0,0 +1,31 @@
+// Box filter downscale of a packed frame, each output pixel is the average of the source pixels it covers
+// Works per byte, so any packed format with 8-bit components is handled
+pub fn downscale(src: &[u8], stride: usize, width: usize, height: usize, pixel_stride: usize, dst_width: usize, dst_height: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(dst_width * dst_height * pixel_stride);
+    let mut sums = vec![0u32; pixel_stride];
+
+    for dy in 0..dst_height {
+        let y1 = dy * height / dst_height;
+        let y2 = ((dy + 1) * height / dst_height).max(y1 + 1);
+
+        for dx in 0..dst_width {
+            let x1 = dx * width / dst_width;
+            let x2 = ((dx + 1) * width / dst_width).max(x1 + 1);
+
+            sums.iter_mut().for_each(|sum| *sum = 0);
+            for y in y1..y2 {
+                let row = &src[y * stride..];
+                for x in x1..x2 {
+                    for (sum, byte) in sums.iter_mut().zip(&row[x * pixel_stride..(x + 1) * pixel_stride]) {
+                        *sum += *byte as u32;
+                    }
+                }
+            }
+
+            let count = ((y2 - y1) * (x2 - x1)) as u32;
+            out.extend(sums.iter().map(|sum| (sum / count) as u8));
+        }
+    }
+
+    out
+}
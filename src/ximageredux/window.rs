@@ -0,0 +1,420 @@
+use anyhow::Result;
+use xcb::{x, Connection, Xid as _};
+
+use crate::{WindowVisibility, WindowType, ToplevelMode};
+
+// Names must stay in the same order as the fields of Atoms
+const ATOM_NAMES: [&str; 13] = [
+    "_NET_WM_NAME",
+    "UTF8_STRING",
+    "COMPOUND_TEXT",
+    "_NET_WM_STATE_FULLSCREEN",
+    "_NET_WM_STATE",
+    "_NET_WM_STATE_HIDDEN",
+    "_NET_CLIENT_LIST",
+    "_NET_WM_WINDOW_TYPE",
+    "_NET_FRAME_EXTENTS",
+    "WM_STATE",
+    "_NET_WM_PID",
+    "_NET_ACTIVE_WINDOW",
+    "_GTK_FRAME_EXTENTS",
+];
+
+// Atoms interned once per connection
+#[derive(Debug, Clone, Copy)]
+pub struct Atoms {
+    pub net_wm_name: x::Atom,
+    pub utf8_string: x::Atom,
+    pub compound_text: x::Atom,
+    pub net_wm_state_fullscreen: x::Atom,
+    pub net_wm_state: x::Atom,
+    pub net_wm_state_hidden: x::Atom,
+    pub net_client_list: x::Atom,
+    pub net_wm_window_type: x::Atom,
+    pub net_frame_extents: x::Atom,
+    pub wm_state: x::Atom,
+    pub net_wm_pid: x::Atom,
+    pub net_active_window: x::Atom,
+    pub gtk_frame_extents: x::Atom
+}
+
+impl Atoms {
+    pub fn intern(conn: &Connection) -> Result<Self> {
+        // Send every request before waiting so this is a single round-trip
+        let cookies: Vec<_> = ATOM_NAMES.iter().map(|name| conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: name.as_bytes()
+        })).collect();
+
+        let mut atoms = Vec::with_capacity(cookies.len());
+        for cookie in cookies {
+            atoms.push(conn.wait_for_reply(cookie)?.atom());
+        }
+
+        let mut atoms = atoms.into_iter();
+        let mut next = || atoms.next().unwrap();
+
+        Ok(Self {
+            net_wm_name: next(),
+            utf8_string: next(),
+            compound_text: next(),
+            net_wm_state_fullscreen: next(),
+            net_wm_state: next(),
+            net_wm_state_hidden: next(),
+            net_client_list: next(),
+            net_wm_window_type: next(),
+            net_frame_extents: next(),
+            wm_state: next(),
+            net_wm_pid: next(),
+            net_active_window: next(),
+            gtk_frame_extents: next()
+        })
+    }
+}
+
+// Reads a whole property, re-requesting with the full length if the first read was truncated
+// Returns None if the property isn't set on the window
+pub fn get_property(conn: &Connection, window: x::Window, property: x::Atom, r#type: x::Atom) -> Result<Option<x::GetPropertyReply>> {
+    let mut long_length = 256;
+
+    loop {
+        let cookie = conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property,
+            r#type,
+            long_offset: 0,
+            long_length
+        });
+
+        let reply = conn.wait_for_reply(cookie)?;
+
+        if reply.r#type() == x::ATOM_NONE {
+            return Ok(None);
+        }
+
+        // Mismatched types come back without a value, let the caller inspect the actual type
+        if reply.bytes_after() == 0 || (r#type != x::ATOM_ANY && reply.r#type() != r#type) {
+            return Ok(Some(reply));
+        }
+
+        // A truncated read always returns exactly long_length 32-bit units
+        long_length += reply.bytes_after().div_ceil(4);
+    }
+}
+
+// Decodes text properties according to their type instead of assuming UTF-8
+pub fn decode_text(atoms: &Atoms, r#type: x::Atom, bytes: &[u8]) -> String {
+    if r#type == atoms.utf8_string {
+        String::from_utf8_lossy(bytes).into_owned()
+    } else if r#type == atoms.compound_text {
+        decode_compound_text(bytes)
+    } else {
+        // STRING is defined as Latin-1, which maps directly onto the first 256 code points
+        bytes.iter().map(|b| *b as char).collect()
+    }
+}
+
+// Handles the common subset of compound text: Latin-1 segments and UTF-8 segments (ESC % G ... ESC % @)
+// Segments in other ISO 2022 charsets have their escape sequences stripped and are decoded as Latin-1
+fn decode_compound_text(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    let mut utf8 = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == 0x1B {
+            match (bytes.get(i + 1), bytes.get(i + 2)) {
+                (Some(b'%'), Some(b'G')) => {
+                    utf8 = true;
+                    i += 3;
+                }
+                (Some(b'%'), Some(b'@')) => {
+                    utf8 = false;
+                    i += 3;
+                }
+                _ => {
+                    // Skip intermediate bytes up to and including the final byte
+                    i += 1;
+                    while i < bytes.len() && (0x20..=0x2F).contains(&bytes[i]) {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            continue;
+        }
+
+        let end = bytes[i..].iter().position(|b| *b == 0x1B).map(|p| i + p).unwrap_or(bytes.len());
+        if utf8 {
+            out.push_str(&String::from_utf8_lossy(&bytes[i..end]));
+        } else {
+            out.extend(bytes[i..end].iter().map(|b| *b as char));
+        }
+        i = end;
+    }
+
+    out
+}
+
+// Undecoded title bytes along with their type
+// Prefers _NET_WM_NAME (always UTF-8) and falls back to WM_NAME with its declared encoding
+pub fn get_title_raw(conn: &Connection, atoms: &Atoms, window: x::Window) -> Result<Option<(x::Atom, Vec<u8>)>> {
+    if let Some(reply) = get_property(conn, window, atoms.net_wm_name, atoms.utf8_string)? {
+        if reply.r#type() == atoms.utf8_string {
+            return Ok(Some((reply.r#type(), reply.value::<u8>().to_vec())));
+        }
+    }
+
+    Ok(get_property(conn, window, x::ATOM_WM_NAME, x::ATOM_ANY)?
+        .filter(|reply| reply.format() == 8)
+        .map(|reply| (reply.r#type(), reply.value::<u8>().to_vec())))
+}
+
+// Invalid UTF-8 is replaced rather than dropping the title
+pub fn get_title(conn: &Connection, atoms: &Atoms, window: x::Window) -> Result<Option<String>> {
+    Ok(get_title_raw(conn, atoms, window)?.map(|(r#type, bytes)| decode_text(atoms, r#type, &bytes)))
+}
+
+// WM_CLASS is the instance name followed by the class name, both NUL-terminated Latin-1
+pub fn get_class(conn: &Connection, window: x::Window) -> Result<Option<(String, String)>> {
+    Ok(get_property(conn, window, x::ATOM_WM_CLASS, x::ATOM_STRING)?
+        .filter(|reply| reply.format() == 8)
+        .and_then(|reply| {
+            let mut parts = reply.value::<u8>().split(|b| *b == 0).map(|part| part.iter().map(|b| *b as char).collect::<String>());
+            Some((parts.next()?, parts.next()?))
+        }))
+}
+
+// ICCCM WM_STATE values
+const WITHDRAWN_STATE: u32 = 0;
+const NORMAL_STATE: u32 = 1;
+const ICONIC_STATE: u32 = 3;
+
+// Client window under a window manager frame, found the same way xwininfo does: the first descendant with WM_STATE
+// Returns the window itself if it already is a client or no client is found
+pub fn find_client(conn: &Connection, atoms: &Atoms, window: x::Window) -> Result<x::Window> {
+    let mut queue = std::collections::VecDeque::from([window]);
+
+    while let Some(candidate) = queue.pop_front() {
+        if get_property(conn, candidate, atoms.wm_state, atoms.wm_state)?.is_some_and(|reply| reply.r#type() == atoms.wm_state) {
+            return Ok(candidate);
+        }
+
+        queue.extend(conn.wait_for_reply(conn.send_request(&x::QueryTree { window: candidate }))?.children());
+    }
+
+    Ok(window)
+}
+
+// Walks up from a possibly nested window to the toplevel the mode asks for
+// Without a window manager nothing has WM_STATE, so client falls back to the window under the root
+pub fn find_toplevel(conn: &Connection, atoms: &Atoms, window: x::Window, mode: ToplevelMode) -> Result<x::Window> {
+    if mode == ToplevelMode::None {
+        return Ok(window);
+    }
+
+    let mut current = window;
+    loop {
+        if mode == ToplevelMode::Client && get_property(conn, current, atoms.wm_state, atoms.wm_state)?.is_some_and(|reply| reply.r#type() == atoms.wm_state) {
+            return Ok(current);
+        }
+
+        let tree = conn.wait_for_reply(conn.send_request(&x::QueryTree { window: current }))?;
+        // The root itself has no parent
+        if tree.parent().is_none() || tree.parent() == tree.root() {
+            return Ok(current);
+        }
+
+        current = tree.parent();
+    }
+}
+
+// Window the window manager says has focus, None when nothing does
+pub fn get_active_window(conn: &Connection, atoms: &Atoms, root: x::Window) -> Result<Option<x::Window>> {
+    Ok(get_property(conn, root, atoms.net_active_window, x::ATOM_WINDOW)?
+        .and_then(|reply| reply.value::<x::Window>().first().copied())
+        .filter(|window| !window.is_none()))
+}
+
+// Process that owns the window, as reported by the client itself
+pub fn get_pid(conn: &Connection, atoms: &Atoms, window: x::Window) -> Result<Option<u32>> {
+    Ok(get_property(conn, window, atoms.net_wm_pid, x::ATOM_CARDINAL)?
+        .filter(|reply| reply.r#type() == x::ATOM_CARDINAL && reply.format() == 32)
+        .and_then(|reply| reply.value::<u32>().first().copied()))
+}
+
+// Visibility and fullscreen state from _NET_WM_STATE, falling back to the ICCCM WM_STATE for visibility
+// Unknown if the window manager sets neither
+pub fn get_state(conn: &Connection, atoms: &Atoms, window: x::Window) -> Result<(WindowVisibility, bool)> {
+    let states: Option<Vec<u32>> = get_property(conn, window, atoms.net_wm_state, x::ATOM_ATOM)?
+        .filter(|reply| reply.r#type() == x::ATOM_ATOM)
+        .map(|reply| reply.value::<u32>().to_vec());
+
+    let fullscreen = states.as_ref().is_some_and(|states| states.contains(&atoms.net_wm_state_fullscreen.resource_id()));
+
+    if states.as_ref().is_some_and(|states| states.contains(&atoms.net_wm_state_hidden.resource_id())) {
+        return Ok((WindowVisibility::Hidden, fullscreen));
+    }
+
+    let wm_state = get_property(conn, window, atoms.wm_state, atoms.wm_state)?
+        .filter(|reply| reply.r#type() == atoms.wm_state && reply.format() == 32)
+        .and_then(|reply| reply.value::<u32>().first().copied());
+
+    let visibility = match (wm_state, states) {
+        (Some(ICONIC_STATE | WITHDRAWN_STATE), _) => WindowVisibility::Hidden,
+        (Some(NORMAL_STATE), _) | (None, Some(_)) => WindowVisibility::Visible,
+        _ => WindowVisibility::Unknown
+    };
+
+    Ok((visibility, fullscreen))
+}
+
+// Width of the window manager's decorations on each side of a client window
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct FrameExtents {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32
+}
+
+fn read_extents(conn: &Connection, window: x::Window, property: x::Atom) -> Result<Option<FrameExtents>> {
+    Ok(get_property(conn, window, property, x::ATOM_CARDINAL)?
+        .filter(|reply| reply.r#type() == x::ATOM_CARDINAL && reply.format() == 32)
+        .and_then(|reply| match reply.value::<u32>() {
+            [left, right, top, bottom, ..] => Some(FrameExtents { left: *left, right: *right, top: *top, bottom: *bottom }),
+            _ => None
+        }))
+}
+
+// _NET_FRAME_EXTENTS is set on the client window, so for a frame window its children are checked too
+pub fn get_frame_extents(conn: &Connection, atoms: &Atoms, window: x::Window) -> Result<Option<FrameExtents>> {
+    if let Some(extents) = read_extents(conn, window, atoms.net_frame_extents)? {
+        return Ok(Some(extents));
+    }
+
+    let tree = conn.wait_for_reply(conn.send_request(&x::QueryTree { window }))?;
+    for child in tree.children() {
+        if let Some(extents) = read_extents(conn, *child, atoms.net_frame_extents)? {
+            return Ok(Some(extents));
+        }
+    }
+
+    Ok(None)
+}
+
+// Shadow margins client-side decorated (GTK) windows draw inside their own geometry
+// Only the window itself is checked, the margins are relative to it
+pub fn get_csd_extents(conn: &Connection, atoms: &Atoms, window: x::Window) -> Result<Option<FrameExtents>> {
+    read_extents(conn, window, atoms.gtk_frame_extents)
+}
+
+// Everything a window picker needs to show about a window
+#[derive(Debug, Clone)]
+pub struct WindowInfo {
+    pub xid: u32,
+    pub title: Option<String>,
+    pub class: Option<(String, String)>,
+    // Relative to the root window
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    pub visibility: WindowVisibility,
+    pub fullscreen: bool
+}
+
+// Windows managed by the window manager, or the root's children if it doesn't publish _NET_CLIENT_LIST
+pub fn client_list(conn: &Connection, atoms: &Atoms, root: x::Window) -> Result<Vec<x::Window>> {
+    Ok(match get_property(conn, root, atoms.net_client_list, x::ATOM_WINDOW)? {
+        Some(reply) if reply.r#type() == x::ATOM_WINDOW => reply.value::<x::Window>().to_vec(),
+        _ => conn.wait_for_reply(conn.send_request(&x::QueryTree { window: root }))?.children().to_vec()
+    })
+}
+
+pub fn get_window_types(conn: &Connection, atoms: &Atoms, window: x::Window) -> Result<Vec<x::Atom>> {
+    Ok(get_property(conn, window, atoms.net_wm_window_type, x::ATOM_ATOM)?
+        .filter(|reply| reply.r#type() == x::ATOM_ATOM)
+        .map(|reply| reply.value::<x::Atom>().to_vec())
+        .unwrap_or_default())
+}
+
+// Atom a window type matches, None for Any
+pub fn window_type_atom(conn: &Connection, window_type: WindowType) -> Result<Option<x::Atom>> {
+    let name = match window_type {
+        WindowType::Any => return Ok(None),
+        WindowType::Normal => "_NET_WM_WINDOW_TYPE_NORMAL",
+        WindowType::Dialog => "_NET_WM_WINDOW_TYPE_DIALOG",
+        WindowType::Utility => "_NET_WM_WINDOW_TYPE_UTILITY",
+        WindowType::Toolbar => "_NET_WM_WINDOW_TYPE_TOOLBAR",
+        WindowType::Menu => "_NET_WM_WINDOW_TYPE_MENU",
+        WindowType::Splash => "_NET_WM_WINDOW_TYPE_SPLASH",
+        WindowType::Dock => "_NET_WM_WINDOW_TYPE_DOCK",
+        WindowType::Desktop => "_NET_WM_WINDOW_TYPE_DESKTOP"
+    };
+
+    let cookie = conn.send_request(&x::InternAtom {
+        only_if_exists: false,
+        name: name.as_bytes()
+    });
+
+    Ok(Some(conn.wait_for_reply(cookie)?.atom()))
+}
+
+// Scans a display (DISPLAY when None) on a connection of its own, for callers that don't have one open
+pub fn list_windows(display: Option<&str>) -> Result<Vec<WindowInfo>> {
+    let (conn, screen_num) = Connection::connect(display)?;
+
+    scan_screen(&conn, screen_num)
+}
+
+pub fn scan_screen(conn: &Connection, screen_num: i32) -> Result<Vec<WindowInfo>> {
+    let root = match conn.get_setup().roots().nth(screen_num as usize) {
+        Some(screen) => screen.root(),
+        None => anyhow::bail!("Screen {} doesn't exist on the server", screen_num)
+    };
+
+    let atoms = Atoms::intern(conn)?;
+
+    scan(conn, &atoms, root)
+}
+
+// Lists the windows managed by the window manager, or the root's children if it doesn't publish _NET_CLIENT_LIST
+pub fn scan(conn: &Connection, atoms: &Atoms, root: x::Window) -> Result<Vec<WindowInfo>> {
+    let windows = client_list(conn, atoms, root)?;
+
+    // Geometry for every window is requested up front so it arrives in one round-trip
+    let geometry_cookies: Vec<_> = windows.iter().map(|window| (
+        conn.send_request(&x::GetGeometry { drawable: x::Drawable::Window(*window) }),
+        conn.send_request(&x::TranslateCoordinates { src_window: *window, dst_window: root, src_x: 0, src_y: 0 })
+    )).collect();
+
+    let mut infos = Vec::with_capacity(windows.len());
+    for (window, (geometry, translate)) in windows.into_iter().zip(geometry_cookies) {
+        // Windows can be destroyed mid-scan, those are skipped
+        let (geometry, translate) = match (conn.wait_for_reply(geometry), conn.wait_for_reply(translate)) {
+            (Ok(geometry), Ok(translate)) => (geometry, translate),
+            _ => continue
+        };
+
+        let (visibility, fullscreen) = match get_state(conn, atoms, window) {
+            Ok(state) => state,
+            Err(_) => continue
+        };
+
+        infos.push(WindowInfo {
+            xid: window.resource_id(),
+            title: get_title(conn, atoms, window).unwrap_or_default(),
+            class: get_class(conn, window).unwrap_or_default(),
+            x: translate.dst_x(),
+            y: translate.dst_y(),
+            width: geometry.width(),
+            height: geometry.height(),
+            visibility,
+            fullscreen
+        });
+    }
+
+    Ok(infos)
+}
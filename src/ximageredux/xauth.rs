@@ -0,0 +1,232 @@
+use std::{ffi::CString, net::{IpAddr, ToSocketAddrs}, os::raw::{c_char, c_int}, path::Path};
+
+use anyhow::{Result, bail};
+use xcb::{ffi::xcb_connection_t, Connection, Extension};
+
+// The only scheme X servers still commonly accept
+const MIT_MAGIC_COOKIE: &[u8] = b"MIT-MAGIC-COOKIE-1";
+
+// Address families of Xauthority entries, from Xauth.h and X.h
+const FAMILY_INTERNET: u16 = 0;
+const FAMILY_INTERNET6: u16 = 6;
+const FAMILY_LOCAL: u16 = 256;
+const FAMILY_WILD: u16 = 65535;
+
+// xcb exposes its auth info connect with a &str cookie, which can't hold binary cookies, so libxcb is called directly
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct xcb_auth_info_t {
+    namelen: c_int,
+    name: *mut c_char,
+    datalen: c_int,
+    data: *mut c_char
+}
+
+extern "C" {
+    fn xcb_connect_to_display_with_auth_info(display: *const c_char, auth: *mut xcb_auth_info_t, screen: *mut c_int) -> *mut xcb_connection_t;
+    fn xcb_connection_has_error(c: *mut xcb_connection_t) -> c_int;
+    fn xcb_disconnect(c: *mut xcb_connection_t);
+}
+
+// Connects with the cookie for the display from an Xauthority file, or the usual way (XAUTHORITY) without one
+pub fn connect(display: Option<&str>, xauthority: Option<&Path>, optional: &[Extension]) -> Result<(Connection, i32)> {
+    let xauthority = match xauthority {
+        Some(xauthority) => xauthority,
+        None => return Ok(Connection::connect_with_extensions(display, &[], optional)?)
+    };
+
+    let display_name = match display.map(str::to_owned).or_else(|| std::env::var("DISPLAY").ok()) {
+        Some(name) => name,
+        None => bail!("No display to connect to, set the display property or DISPLAY")
+    };
+
+    let (host, number) = match xcb::parse_display(&display_name) {
+        Some(info) => (info.host, info.display.to_string()),
+        None => bail!("Invalid display name {:?}", display_name)
+    };
+
+    let mut cookie = match find_cookie(&std::fs::read(xauthority)?, &auth_addresses(&host), number.as_bytes()) {
+        Some(cookie) => cookie,
+        None => bail!("{} has no {} entry for {:?}", xauthority.display(), String::from_utf8_lossy(MIT_MAGIC_COOKIE), display_name)
+    };
+
+    let display_name = CString::new(display_name)?;
+    let mut name = MIT_MAGIC_COOKIE.to_vec();
+    let mut auth = xcb_auth_info_t {
+        namelen: name.len() as c_int,
+        name: name.as_mut_ptr() as *mut c_char,
+        datalen: cookie.len() as c_int,
+        data: cookie.as_mut_ptr() as *mut c_char
+    };
+    let mut screen_num: c_int = 0;
+
+    unsafe {
+        // Never null, failures are reported through the error state
+        let conn = xcb_connect_to_display_with_auth_info(display_name.as_ptr(), &mut auth, &mut screen_num);
+
+        let error = xcb_connection_has_error(conn);
+        if error != 0 {
+            xcb_disconnect(conn);
+            bail!("Failed to connect to {:?} with {}: error {}", display_name, xauthority.display(), error);
+        }
+
+        Ok((Connection::from_raw_conn_and_extensions(conn, &[], optional), screen_num))
+    }
+}
+
+// Family and address the server knows the connection by, worked out the way libxcb does before asking libXau
+// Unix sockets and loopback TCP are local, which Xauthority files key by this machine's hostname
+fn auth_addresses(host: &str) -> Vec<(u16, Vec<u8>)> {
+    let local = || vec![(FAMILY_LOCAL, hostname())];
+
+    if host.is_empty() || host == "unix" || host.starts_with('/') {
+        return local();
+    }
+
+    let ips: Vec<IpAddr> = match host.parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => (host, 0).to_socket_addrs().map(|addrs| addrs.map(|addr| addr.ip()).collect()).unwrap_or_default()
+    };
+
+    if ips.iter().any(IpAddr::is_loopback) {
+        return local();
+    }
+
+    ips.into_iter().map(|ip| match ip {
+        IpAddr::V4(ip) => (FAMILY_INTERNET, ip.octets().to_vec()),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => (FAMILY_INTERNET, ip.octets().to_vec()),
+            None => (FAMILY_INTERNET6, ip.octets().to_vec())
+        }
+    }).collect()
+}
+
+fn hostname() -> Vec<u8> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut c_char, buf.len()) } != 0 {
+        return Vec::new();
+    }
+
+    buf.split(|b| *b == 0).next().unwrap_or_default().to_vec()
+}
+
+// Entries are family, address, display number, auth name and auth data, each field but family length-prefixed, big-endian
+// Like XauGetBestAuthByAddr, an entry matches when its family and address are one of addresses or it's FamilyWild,
+// and its display number is the display's or empty
+fn find_cookie(file: &[u8], addresses: &[(u16, Vec<u8>)], number: &[u8]) -> Option<Vec<u8>> {
+    let mut rest = file;
+
+    while !rest.is_empty() {
+        let family = read_u16(&mut rest)?;
+
+        let mut fields = [&[][..]; 4];
+        for field in &mut fields {
+            let len = read_u16(&mut rest)? as usize;
+            if rest.len() < len {
+                return None;
+            }
+
+            let (value, tail) = rest.split_at(len);
+            *field = value;
+            rest = tail;
+        }
+
+        let [address, entry_number, name, data] = fields;
+        let address_matches = family == FAMILY_WILD || addresses.iter().any(|(f, a)| *f == family && a == address);
+        if address_matches && (entry_number.is_empty() || entry_number == number) && name == MIT_MAGIC_COOKIE {
+            return Some(data.to_vec());
+        }
+    }
+
+    None
+}
+
+fn read_u16(rest: &mut &[u8]) -> Option<u16> {
+    let (value, tail) = rest.split_first_chunk::<2>()?;
+    *rest = tail;
+    Some(u16::from_be_bytes(*value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(family: u16, address: &[u8], number: &[u8], name: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut entry = family.to_be_bytes().to_vec();
+        for field in [address, number, name, data] {
+            entry.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            entry.extend_from_slice(field);
+        }
+
+        entry
+    }
+
+    fn local(host: &[u8]) -> Vec<(u16, Vec<u8>)> {
+        vec![(FAMILY_LOCAL, host.to_vec())]
+    }
+
+    #[test]
+    fn picks_the_entry_for_the_host() {
+        let file = [
+            entry(FAMILY_LOCAL, b"remote", b"0", MIT_MAGIC_COOKIE, b"wrong"),
+            entry(FAMILY_LOCAL, b"host", b"0", MIT_MAGIC_COOKIE, b"right")
+        ].concat();
+
+        assert_eq!(find_cookie(&file, &local(b"host"), b"0"), Some(b"right".to_vec()));
+        assert_eq!(find_cookie(&file, &local(b"other"), b"0"), None);
+    }
+
+    #[test]
+    fn matches_the_display_number() {
+        let file = [
+            entry(FAMILY_LOCAL, b"host", b"1", MIT_MAGIC_COOKIE, b"one"),
+            entry(FAMILY_LOCAL, b"host", b"", MIT_MAGIC_COOKIE, b"any")
+        ].concat();
+
+        assert_eq!(find_cookie(&file, &local(b"host"), b"1"), Some(b"one".to_vec()));
+        assert_eq!(find_cookie(&file, &local(b"host"), b"2"), Some(b"any".to_vec()));
+    }
+
+    #[test]
+    fn matches_family_and_address() {
+        let file = [
+            entry(FAMILY_LOCAL, &[10, 0, 0, 1], b"0", MIT_MAGIC_COOKIE, b"local"),
+            entry(FAMILY_INTERNET, &[10, 0, 0, 1], b"0", MIT_MAGIC_COOKIE, b"inet")
+        ].concat();
+
+        assert_eq!(find_cookie(&file, &[(FAMILY_INTERNET, vec![10, 0, 0, 1])], b"0"), Some(b"inet".to_vec()));
+        assert_eq!(find_cookie(&file, &[(FAMILY_INTERNET6, vec![10, 0, 0, 1])], b"0"), None);
+    }
+
+    #[test]
+    fn wild_entries_match_any_address() {
+        let file = entry(FAMILY_WILD, b"", b"0", MIT_MAGIC_COOKIE, b"wild");
+        assert_eq!(find_cookie(&file, &local(b"host"), b"0"), Some(b"wild".to_vec()));
+    }
+
+    #[test]
+    fn skips_other_schemes() {
+        let file = [
+            entry(FAMILY_LOCAL, b"host", b"0", b"XDM-AUTHORIZATION-1", b"xdm"),
+            entry(FAMILY_LOCAL, b"host", b"0", MIT_MAGIC_COOKIE, b"mit")
+        ].concat();
+
+        assert_eq!(find_cookie(&file, &local(b"host"), b"0"), Some(b"mit".to_vec()));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let file = entry(FAMILY_LOCAL, b"host", b"0", MIT_MAGIC_COOKIE, b"cookie");
+        assert_eq!(find_cookie(&file[..file.len() - 1], &local(b"host"), b"0"), None);
+        assert_eq!(find_cookie(&file[..1], &local(b"host"), b"0"), None);
+    }
+
+    #[test]
+    fn local_hosts_use_the_hostname() {
+        assert_eq!(auth_addresses(""), local(&hostname()));
+        assert_eq!(auth_addresses("unix"), local(&hostname()));
+        assert_eq!(auth_addresses("127.0.0.1"), local(&hostname()));
+        assert_eq!(auth_addresses("10.1.2.3"), vec![(FAMILY_INTERNET, vec![10, 1, 2, 3])]);
+        assert_eq!(auth_addresses("::ffff:10.1.2.3"), vec![(FAMILY_INTERNET, vec![10, 1, 2, 3])]);
+    }
+}
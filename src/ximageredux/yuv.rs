@@ -0,0 +1,94 @@
+use anyhow::{Result, bail};
+use gst_video::{VideoFormat, VideoFrameRef, VideoInfo};
+
+use crate::Colorimetry;
+
+use super::cursor;
+
+// Limited range coefficients scaled by 256, rows are Y, U, V and columns are R, G, B
+fn coefficients(colorimetry: Colorimetry) -> [[i32; 3]; 3] {
+    match colorimetry {
+        Colorimetry::Bt601 => [[66, 129, 25], [-38, -74, 112], [112, -94, -18]],
+        Colorimetry::Bt709 => [[47, 157, 16], [-26, -87, 112], [112, -102, -10]]
+    }
+}
+
+fn apply(row: [i32; 3], rgb: [i32; 3], offset: i32) -> u8 {
+    (((row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2] + 128) >> 8) + offset).clamp(0, 255) as u8
+}
+
+// Converts a packed 8-bit RGB frame into an I420 or NV12 frame described by out_info
+// Chroma is the average of each 2x2 block, so it's sited in the center of the block
+pub fn convert(src: &gst::BufferRef, info: &VideoInfo, out_info: &VideoInfo, colorimetry: Colorimetry) -> Result<gst::Buffer> {
+    let (pixel_stride, [r, g, b]) = match cursor::rgb_offsets(&info.format_info()) {
+        Some(offsets) => offsets,
+        None => bail!("Can't convert {} to YUV", info.format())
+    };
+
+    let matrix = coefficients(colorimetry);
+    let (width, height) = (info.width() as usize, info.height() as usize);
+
+    let frame = VideoFrameRef::from_buffer_ref_readable(src, info)?;
+    let src_stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data(0)?;
+
+    let rgb = |x: usize, y: usize| {
+        let pixel = &data[y * src_stride + x * pixel_stride..];
+        [pixel[r] as i32, pixel[g] as i32, pixel[b] as i32]
+    };
+
+    let mut out = gst::Buffer::with_size(out_info.size())?;
+    {
+        let out = out.get_mut().unwrap();
+        out.set_duration(src.duration());
+
+        let mut out_frame = VideoFrameRef::from_buffer_ref_writable(out, out_info)?;
+        let strides: Vec<usize> = out_frame.plane_stride().iter().map(|s| *s as usize).collect();
+
+        let luma = out_frame.plane_data_mut(0)?;
+        for y in 0..height {
+            for x in 0..width {
+                luma[y * strides[0] + x] = apply(matrix[0], rgb(x, y), 16);
+            }
+        }
+
+        // Odd dimensions give the last chroma sample a 1-pixel wide or tall block
+        let chroma: Vec<(usize, usize, u8, u8)> = (0..height.div_ceil(2)).flat_map(|cy| (0..width.div_ceil(2)).map(move |cx| (cx, cy))).map(|(cx, cy)| {
+            let mut sum = [0; 3];
+            let mut count = 0;
+            for y in cy * 2..(cy * 2 + 2).min(height) {
+                for x in cx * 2..(cx * 2 + 2).min(width) {
+                    sum.iter_mut().zip(rgb(x, y)).for_each(|(sum, c)| *sum += c);
+                    count += 1;
+                }
+            }
+
+            let average = sum.map(|sum| sum / count);
+            (cx, cy, apply(matrix[1], average, 128), apply(matrix[2], average, 128))
+        }).collect();
+
+        match out_info.format() {
+            VideoFormat::I420 => {
+                let u = out_frame.plane_data_mut(1)?;
+                for (cx, cy, value, _) in &chroma {
+                    u[cy * strides[1] + cx] = *value;
+                }
+
+                let v = out_frame.plane_data_mut(2)?;
+                for (cx, cy, _, value) in &chroma {
+                    v[cy * strides[2] + cx] = *value;
+                }
+            }
+            VideoFormat::Nv12 => {
+                let uv = out_frame.plane_data_mut(1)?;
+                for (cx, cy, u, v) in &chroma {
+                    uv[cy * strides[1] + cx * 2] = *u;
+                    uv[cy * strides[1] + cx * 2 + 1] = *v;
+                }
+            }
+            format => bail!("Can't convert to {}", format)
+        }
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,271 @@
+// These need a running X server and are skipped when DISPLAY can't be reached
+use std::{collections::HashMap, sync::Once};
+
+use gst::prelude::*;
+use xcb::{x, Xid as _};
+
+fn init() {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        ximageredux::plugin_register_static().unwrap();
+    });
+}
+
+// A plain window to capture, None if there's no X server to create it on
+fn create_window(width: u16, height: u16, map: bool) -> Option<(xcb::Connection, x::Window)> {
+    // X-Resource is optional, tests that count server resources check for it themselves
+    let (conn, screen_num) = match xcb::Connection::connect_with_extensions(None, &[], &[xcb::Extension::Res]) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Skipping, no X server: {}", e);
+            return None;
+        }
+    };
+
+    let screen = conn.get_setup().roots().nth(screen_num as usize).unwrap();
+    let window: x::Window = conn.generate_id();
+
+    conn.send_and_check_request(&x::CreateWindow {
+        depth: x::COPY_FROM_PARENT as u8,
+        wid: window,
+        parent: screen.root(),
+        x: 0,
+        y: 0,
+        width,
+        height,
+        border_width: 0,
+        class: x::WindowClass::InputOutput,
+        visual: screen.root_visual(),
+        value_list: &[x::Cw::BackPixel(screen.white_pixel())]
+    }).unwrap();
+
+    if map {
+        conn.send_and_check_request(&x::MapWindow { window }).unwrap();
+    }
+
+    Some((conn, window))
+}
+
+// Source capturing the window into an appsink, so tests can look at what's pushed
+fn capture_pipeline(window: x::Window) -> (gst::Pipeline, gst::Element, gst_app::AppSink) {
+    init();
+
+    let pipeline = gst::Pipeline::new(None);
+    let src = gst::ElementFactory::make("ximageredux")
+        .property("xid", window.resource_id())
+        .build()
+        .unwrap();
+    let sink = gst_app::AppSink::builder().sync(false).build();
+
+    pipeline.add_many(&[&src, sink.upcast_ref()]).unwrap();
+    src.link(&sink).unwrap();
+
+    (pipeline, src, sink)
+}
+
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+// Resources held by every client on the server, by type, so resources of the element's connections are included
+fn server_resources(conn: &xcb::Connection) -> HashMap<x::Atom, u32> {
+    let clients = conn.wait_for_reply(conn.send_request(&xcb::res::QueryClients {})).unwrap();
+    let mut totals = HashMap::new();
+
+    for client in clients.clients() {
+        let resources = conn.wait_for_reply(conn.send_request(&xcb::res::QueryClientResources {
+            xid: client.resource_base
+        })).unwrap();
+
+        for resource in resources.types() {
+            *totals.entry(resource.resource_type).or_default() += resource.count;
+        }
+    }
+
+    totals
+}
+
+// Segments this process created that are still around
+fn shm_segments() -> usize {
+    let pid = std::process::id().to_string();
+
+    std::fs::read_to_string("/proc/sysvipc/shm").unwrap()
+        .lines()
+        .skip(1)
+        .filter(|line| line.split_whitespace().nth(4) == Some(pid.as_str()))
+        .count()
+}
+
+#[test]
+fn start_stop_releases_server_resources() {
+    let Some((conn, window)) = create_window(64, 48, true) else { return };
+    let (pipeline, src, sink) = capture_pipeline(window);
+
+    src.set_property("use-shm", true);
+    src.set_property("offscreen", true);
+    src.set_property("redirect-subwindows", true);
+    src.set_property("backing-store", ximageredux::BackingStore::Always);
+
+    let original = conn.wait_for_reply(conn.send_request(&x::GetWindowAttributes { window })).unwrap();
+
+    // One round first so lazily created state, like the plugin's own caches, isn't counted as a leak
+    pipeline.set_state(gst::State::Playing).unwrap();
+    sink.try_pull_sample(gst::ClockTime::from_seconds(5)).expect("no frame captured");
+    pipeline.set_state(gst::State::Null).unwrap();
+
+    let fds = open_fds();
+    let segments = shm_segments();
+
+    for _ in 0..50 {
+        pipeline.set_state(gst::State::Playing).unwrap();
+        sink.try_pull_sample(gst::ClockTime::from_seconds(5)).expect("no frame captured");
+        pipeline.set_state(gst::State::Null).unwrap();
+    }
+
+    assert_eq!(open_fds(), fds, "connections leaked");
+    assert_eq!(shm_segments(), segments, "shared memory segments leaked");
+
+    let attributes = conn.wait_for_reply(conn.send_request(&x::GetWindowAttributes { window })).unwrap();
+    assert_eq!(attributes.backing_store(), original.backing_store());
+
+    if !conn.active_extensions().any(|ext| ext == xcb::Extension::Res) {
+        eprintln!("Skipping server resource counts, X-Resource is unavailable");
+        return;
+    }
+
+    // Closing a connection frees everything it owns, so the server-side check stops at READY where it stays open
+    pipeline.set_state(gst::State::Ready).unwrap();
+    let resources = server_resources(&conn);
+
+    for _ in 0..50 {
+        pipeline.set_state(gst::State::Playing).unwrap();
+        sink.try_pull_sample(gst::ClockTime::from_seconds(5)).expect("no frame captured");
+        pipeline.set_state(gst::State::Ready).unwrap();
+    }
+
+    // Damage objects, named Composite pixmaps and SHM segments are all counted here
+    let leaked: Vec<_> = server_resources(&conn).into_iter()
+        .filter(|(kind, count)| *count > resources.get(kind).copied().unwrap_or(0))
+        .map(|(kind, count)| {
+            let name = conn.wait_for_reply(conn.send_request(&x::GetAtomName { atom: kind })).unwrap();
+            format!("{} {} -> {}", name.name(), resources.get(&kind).copied().unwrap_or(0), count)
+        })
+        .collect();
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(leaked.is_empty(), "server resources leaked: {:?}", leaked);
+}
+
+// Negotiation starts as soon as the state changes, before anything else has touched the element
+#[test]
+fn negotiates_right_after_setting_properties() {
+    let Some((_conn, window)) = create_window(64, 48, true) else { return };
+    init();
+
+    let pipeline = gst::Pipeline::new(None);
+    let src = gst::ElementFactory::make("ximageredux")
+        .property("xid", window.resource_id())
+        .property("min-frame-interval-ms", 10u32)
+        .property("pixel-aspect-ratio", gst::Fraction::new(1, 1))
+        .build()
+        .unwrap();
+    let filter = gst::ElementFactory::make("capsfilter")
+        .property("caps", gst::Caps::builder("video/x-raw").field("framerate", gst::Fraction::new(30, 1)).build())
+        .build()
+        .unwrap();
+    let sink = gst_app::AppSink::builder().sync(false).build();
+
+    pipeline.add_many(&[&src, &filter, sink.upcast_ref()]).unwrap();
+    gst::Element::link_many(&[&src, &filter, sink.upcast_ref()]).unwrap();
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+    let sample = sink.try_pull_sample(gst::ClockTime::from_seconds(5)).expect("negotiation failed");
+    pipeline.set_state(gst::State::Null).unwrap();
+
+    let info = gst_video::VideoInfo::from_caps(sample.caps().unwrap()).unwrap();
+    assert_eq!((info.width(), info.height()), (64, 48));
+    assert_eq!(info.fps(), gst::Fraction::new(30, 1));
+}
+
+#[test]
+fn caps_query_survives_unmapped_and_destroyed_windows() {
+    let Some((conn, window)) = create_window(64, 48, false) else { return };
+    let (pipeline, src, _sink) = capture_pipeline(window);
+    let pad = src.static_pad("src").unwrap();
+
+    // An unmapped window still has a geometry, so it gets real caps
+    pipeline.set_state(gst::State::Ready).unwrap();
+    assert!(!pad.query_caps(None).is_empty());
+    pipeline.set_state(gst::State::Null).unwrap();
+
+    // Once the window is gone its size can't be read, which must not fail the caps query
+    let (pipeline, src, _sink) = capture_pipeline(window);
+    let pad = src.static_pad("src").unwrap();
+
+    pipeline.set_state(gst::State::Ready).unwrap();
+    conn.send_and_check_request(&x::DestroyWindow { window }).unwrap();
+    assert_eq!(pad.query_caps(None), pad.pad_template_caps());
+    pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn failed_capture_after_resize_pushes_no_stale_frame() {
+    let Some((conn, window)) = create_window(64, 48, true) else { return };
+    let (pipeline, src, sink) = capture_pipeline(window);
+
+    src.set_property("flush-on-resize", true);
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+    sink.try_pull_sample(gst::ClockTime::from_seconds(5)).expect("no frame captured");
+
+    // GetImage fails on an unmapped window, so the capture right after the resize can't succeed
+    conn.send_request(&x::ConfigureWindow {
+        window,
+        value_list: &[x::ConfigWindow::Width(96), x::ConfigWindow::Height(72)]
+    });
+    conn.send_and_check_request(&x::UnmapWindow { window }).unwrap();
+
+    // The stream may error out, but nothing it pushes may be smaller than its caps say
+    for _ in 0..50 {
+        let Some(sample) = sink.try_pull_sample(gst::ClockTime::from_seconds(2)) else { break };
+        let info = gst_video::VideoInfo::from_caps(sample.caps().unwrap()).unwrap();
+        let buffer = sample.buffer().unwrap();
+
+        assert!(gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, &info).is_ok(),
+            "{} byte buffer pushed with {}x{} caps", buffer.size(), info.width(), info.height());
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[cfg(feature = "gl")]
+#[test]
+fn offers_gl_memory_only_offscreen() {
+    let Some((conn, window)) = create_window(64, 48, true) else { return };
+    let is_gl = |features: &gst::CapsFeaturesRef| features.contains("memory:GLMemory");
+
+    let (pipeline, src, _sink) = capture_pipeline(window);
+    pipeline.set_state(gst::State::Ready).unwrap();
+    let caps = src.static_pad("src").unwrap().query_caps(None);
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(!caps.iter_with_features().any(|(_, features)| is_gl(features)), "GLMemory offered without offscreen: {}", caps);
+
+    if conn.wait_for_reply(conn.send_request(&x::QueryExtension { name: b"Composite" })).map_or(true, |ext| !ext.present()) {
+        eprintln!("Skipping GLMemory caps, Composite is unavailable");
+        return;
+    }
+
+    let (pipeline, src, _sink) = capture_pipeline(window);
+    src.set_property("offscreen", true);
+    pipeline.set_state(gst::State::Ready).unwrap();
+    let caps = src.static_pad("src").unwrap().query_caps(None);
+    pipeline.set_state(gst::State::Null).unwrap();
+
+    // Ahead of system memory so GL sinks pick it, with the window's size
+    let (structure, features) = caps.iter_with_features().next().unwrap();
+    assert!(is_gl(features), "GLMemory isn't preferred: {}", caps);
+    assert_eq!(structure.get::<&str>("format").unwrap(), "RGBA");
+    assert_eq!((structure.get::<i32>("width").unwrap(), structure.get::<i32>("height").unwrap()), (64, 48));
+}